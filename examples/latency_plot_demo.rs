@@ -68,7 +68,14 @@ fn main() -> Result<()> {
         .enumerate()
         .map(|(i, &latency)| {
             let timestamp = UNIX_EPOCH + Duration::from_secs(base_time + (i as u64 * 3));
-            plotting::TimestampedLatency { timestamp, latency }
+            plotting::TimestampedLatency {
+                timestamp,
+                latency,
+                phases: plotting::PhaseLatency {
+                    inclusion: latency,
+                    ..Default::default()
+                },
+            }
         })
         .collect();
     
@@ -80,8 +87,48 @@ fn main() -> Result<()> {
         &stats,
         None,  // Auto-calculate optimal window based on data
         Some(1000.0),
+        plotting::ReportAssets::Cdn,
     )?;
     println!("\nCombined plot saved to: {combined_path:?}");
 
+    // Same run after a hypothetical node change: slow mode mostly gone
+    let after: Vec<plotting::TimestampedLatency> = timestamped_latencies
+        .iter()
+        .map(|item| {
+            let latency = if item.latency > Duration::from_millis(2000) && rng.gen_bool(0.8) {
+                item.latency / 5
+            } else {
+                item.latency
+            };
+            plotting::TimestampedLatency {
+                timestamp: item.timestamp + Duration::from_secs(3600),
+                latency,
+                phases: plotting::PhaseLatency {
+                    inclusion: latency,
+                    ..Default::default()
+                },
+            }
+        })
+        .collect();
+
+    let comparison_path = PathBuf::from("demo_comparison_plots.html");
+    plotting::generate_comparison_plots(
+        &[
+            plotting::LatencyDataset {
+                label: "before".to_string(),
+                data: timestamped_latencies,
+            },
+            plotting::LatencyDataset {
+                label: "after".to_string(),
+                data: after,
+            },
+        ],
+        comparison_path.clone(),
+        None,
+        Some(1000.0),
+        plotting::ReportAssets::Cdn,
+    )?;
+    println!("Comparison plot saved to: {comparison_path:?}");
+
     Ok(())
 }
\ No newline at end of file
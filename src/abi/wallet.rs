@@ -20,4 +20,30 @@ pub struct GetWalletFunctionOutput {
 
 pub fn get_wallet() -> &'static ton_abi::Function {
     wallet_factory().function("get_wallet").unwrap()
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, PackAbiPlain, KnownParamTypePlain)]
+pub struct BatchEverWalletDeployInput {
+    #[abi(name = "_index", uint64)]
+    pub index: u64,
+    #[abi(name = "_balance", uint128)]
+    pub balance: u128,
+    #[abi(name = "_publicKey", uint256)]
+    pub public_key: UInt256,
+    #[abi(name = "_count", uint64)]
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, UnpackAbiPlain, KnownParamTypePlain)]
+pub struct GetDeployedFunctionOutput {
+    #[abi(uint64)]
+    pub deployed: u64,
+}
+
+pub fn batch_ever_wallet_deploy() -> &'static ton_abi::Function {
+    wallet_factory().function("batchEverWalletDeploy").unwrap()
+}
+
+pub fn get_deployed() -> &'static ton_abi::Function {
+    wallet_factory().function("get_deployed").unwrap()
+}
@@ -0,0 +1,251 @@
+//! Archive of signed messages produced by `--record` and consumed by `replay`.
+//!
+//! Layout: `NKRA` magic and a version byte, then one frame per message:
+//! `[u64 BE send offset in microseconds][u32 BE boc length][message boc]`.
+
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use everscale_rpc_client::RpcClient;
+use tokio::sync::{mpsc, oneshot};
+use ton_block::{Deserializable, Message, Serializable};
+
+use crate::endpoints;
+
+const MAGIC: &[u8; 4] = b"NKRA";
+const VERSION: u8 = 1;
+
+/// Expiration used for regular (non recorded) messages.
+const DEFAULT_MESSAGE_TTL: Duration = Duration::from_secs(60);
+
+pub struct ArchiveWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(mut inner: W) -> Result<Self> {
+        inner.write_all(MAGIC)?;
+        inner.write_all(&[VERSION])?;
+        Ok(Self { inner })
+    }
+
+    pub fn write(&mut self, offset: Duration, message: &Message) -> Result<()> {
+        let boc = message.write_to_bytes()?;
+        self.inner
+            .write_all(&(offset.as_micros() as u64).to_be_bytes())?;
+        self.inner.write_all(&(boc.len() as u32).to_be_bytes())?;
+        self.inner.write_all(&boc)?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+pub struct ArchiveReader<R: Read> {
+    inner: R,
+}
+
+impl<R: Read> ArchiveReader<R> {
+    pub fn new(mut inner: R) -> Result<Self> {
+        let mut header = [0u8; 5];
+        inner
+            .read_exact(&mut header)
+            .context("Failed to read archive header")?;
+        if &header[..4] != MAGIC {
+            anyhow::bail!("Not a message archive");
+        }
+        if header[4] != VERSION {
+            anyhow::bail!("Unsupported archive version {}", header[4]);
+        }
+        Ok(Self { inner })
+    }
+
+    fn read_frame(&mut self) -> Result<Option<(Duration, Message)>> {
+        let mut offset = [0u8; 8];
+        let mut read = 0;
+        while read < offset.len() {
+            match self.inner.read(&mut offset[read..])? {
+                0 if read == 0 => return Ok(None),
+                0 => anyhow::bail!("Truncated archive"),
+                n => read += n,
+            }
+        }
+
+        let mut len = [0u8; 4];
+        self.inner
+            .read_exact(&mut len)
+            .context("Truncated archive")?;
+        let mut boc = vec![0u8; u32::from_be_bytes(len) as usize];
+        self.inner
+            .read_exact(&mut boc)
+            .context("Truncated archive")?;
+
+        let message = Message::construct_from_bytes(&boc).context("Invalid message boc")?;
+        Ok(Some((
+            Duration::from_micros(u64::from_be_bytes(offset)),
+            message,
+        )))
+    }
+}
+
+impl<R: Read> Iterator for ArchiveReader<R> {
+    type Item = Result<(Duration, Message)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+enum WriterCommand {
+    Record(Duration, Message),
+    Finish(oneshot::Sender<Result<()>>),
+}
+
+struct Recorder {
+    tx: mpsc::UnboundedSender<WriterCommand>,
+    start: Instant,
+    ttl: Duration,
+}
+
+static RECORDER: OnceLock<Recorder> = OnceLock::new();
+
+/// Switches [`broadcast`] to writing messages into `path` instead of sending them.
+pub fn start_recording(path: &Path, ttl: Duration) -> Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to create archive {path:?}"))?;
+    let mut writer = ArchiveWriter::new(BufWriter::new(file))?;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    tokio::task::spawn_blocking(move || {
+        let mut error = None;
+        while let Some(command) = rx.blocking_recv() {
+            match command {
+                WriterCommand::Record(offset, message) => {
+                    if error.is_none() {
+                        error = writer.write(offset, &message).err();
+                    }
+                }
+                WriterCommand::Finish(reply) => {
+                    let result = match error.take() {
+                        Some(e) => Err(e),
+                        None => writer.finish().map(|_| ()),
+                    };
+                    let _ = reply.send(result);
+                    return;
+                }
+            }
+        }
+    });
+
+    RECORDER
+        .set(Recorder {
+            tx,
+            start: Instant::now(),
+            ttl,
+        })
+        .map_err(|_| anyhow::anyhow!("Recording is already started"))?;
+
+    log::info!("Recording messages to {:?} instead of broadcasting", path);
+    Ok(())
+}
+
+pub fn is_recording() -> bool {
+    RECORDER.get().is_some()
+}
+
+/// How long signed messages stay valid. Recorded messages live longer so they can be replayed.
+pub fn message_ttl() -> Duration {
+    RECORDER
+        .get()
+        .map(|r| r.ttl)
+        .unwrap_or(DEFAULT_MESSAGE_TTL)
+}
+
+/// Broadcasts the message, or stores it with its send offset when recording.
+pub async fn broadcast(client: &RpcClient, message: Message) -> Result<()> {
+    match RECORDER.get() {
+        Some(recorder) => recorder
+            .tx
+            .send(WriterCommand::Record(recorder.start.elapsed(), message))
+            .map_err(|_| anyhow::anyhow!("Recording is already finished")),
+        None => {
+            endpoints::call(client, |client| async move {
+                client.broadcast_message(message).await
+            })
+            .await
+        }
+    }
+}
+
+/// Flushes the archive, must be called once the run is over.
+pub async fn finish_recording() -> Result<()> {
+    let Some(recorder) = RECORDER.get() else {
+        return Ok(());
+    };
+    let (tx, rx) = oneshot::channel();
+    recorder
+        .tx
+        .send(WriterCommand::Finish(tx))
+        .map_err(|_| anyhow::anyhow!("Recording is already finished"))?;
+    rx.await.context("Archive writer stopped")?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use ton_block::{ExternalInboundMessageHeader, MsgAddressInt};
+    use ton_types::{BuilderData, SliceData};
+
+    fn message(index: u32) -> Message {
+        let mut body = BuilderData::new();
+        body.append_u32(index).unwrap();
+        Message::with_ext_in_header_and_body(
+            ExternalInboundMessageHeader {
+                dst: MsgAddressInt::from_str(&format!("0:{index:064x}")).unwrap(),
+                ..Default::default()
+            },
+            SliceData::load_builder(body).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_archive_roundtrip() {
+        let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+        for i in 0..10 {
+            writer
+                .write(Duration::from_millis(i as u64 * 100), &message(i))
+                .unwrap();
+        }
+        let data = writer.finish().unwrap();
+
+        let frames = ArchiveReader::new(data.as_slice())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(frames.len(), 10);
+        for (i, (offset, msg)) in frames.into_iter().enumerate() {
+            assert_eq!(offset, Duration::from_millis(i as u64 * 100));
+            assert_eq!(msg.hash().unwrap(), message(i as u32).hash().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_truncated_archive() {
+        let mut writer = ArchiveWriter::new(Vec::new()).unwrap();
+        writer.write(Duration::ZERO, &message(0)).unwrap();
+        let mut data = writer.finish().unwrap();
+        data.truncate(data.len() - 1);
+
+        let mut reader = ArchiveReader::new(data.as_slice()).unwrap();
+        assert!(reader.next().unwrap().is_err());
+
+        assert!(ArchiveReader::new(&b"junk!"[..]).is_err());
+    }
+}
@@ -1,6 +1,8 @@
 use crate::abi::dudos_factory;
+use crate::archive;
 use crate::build_payload::{get_dag_payload, get_stats};
-use crate::models::GenericDeploymentInfo;
+use crate::deployments::{Deployments, Role};
+use crate::report::RunStats;
 use crate::util::TestEnv;
 use crate::Args;
 use anyhow::{Context, Result};
@@ -11,12 +13,13 @@ use governor::Jitter;
 use nekoton::transport::models::ExistingContract;
 use nekoton_abi::{FunctionExt, UnpackAbiPlain};
 use nekoton_utils::SimpleClock;
+use serde::Serialize;
 use std::sync::atomic::AtomicU64;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use ton_abi::{Token, TokenValue, Uint};
 use ton_block::MsgAddressInt;
 
-#[derive(Parser, Debug, Clone)]
+#[derive(Parser, Debug, Clone, Serialize)]
 pub struct DagTestArgs {
     #[clap(short, long)]
     total_wallets: u32,
@@ -34,43 +37,18 @@ pub struct DagTestArgs {
     #[clap(long, default_value = "false")]
     rand_cell: bool,
 }
-pub async fn run(swap_args: DagTestArgs, common_args: Args, client: RpcClient) -> Result<()> {
-    let base_deployments_path = common_args.project_root.join("deployments");
-    let network_deployments_path = if let Some(network_name) = &common_args.network {
-        base_deployments_path.join(network_name)
-    } else {
-        base_deployments_path
-    };
-
-    if common_args.network.is_some() && !network_deployments_path.is_dir() {
-        return Err(anyhow::anyhow!(
-            "Specified network deployment directory not found: {:?}",
-            network_deployments_path
-        ));
-    }
-
-    log::info!("Using deployments path: {:?}", network_deployments_path);
-
-    let factory_abi = walkdir::WalkDir::new(&network_deployments_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| e.path().extension().map(|e| e == "json").unwrap_or(false))
-        .find(|e| {
-            e.path()
-                .file_name()
-                .and_then(|n| n.to_str())
-                .map(|n| n.to_lowercase().contains("factory"))
-                .unwrap_or(false)
-        })
-        .context("No factory abi")?;
-    let factory: GenericDeploymentInfo =
-        serde_json::from_slice(&std::fs::read(factory_abi.path())?)?;
+pub async fn run(
+    swap_args: DagTestArgs,
+    common_args: Args,
+    client: RpcClient,
+    stats: RunStats,
+) -> Result<()> {
+    let factory = Deployments::load(&common_args)?.one(Role::DudosFactory)?;
     let recievers = get_wallets(client.clone(), &factory.address, swap_args.total_wallets)
         .await
         .context("Failed to get wallets")?;
 
-    spawn_ddos_jobs(&swap_args, client, recievers, common_args).await?;
+    spawn_ddos_jobs(&swap_args, client, recievers, common_args, stats).await?;
 
     Ok(())
 }
@@ -80,6 +58,7 @@ async fn spawn_ddos_jobs(
     client: RpcClient,
     recievers: Vec<MsgAddressInt>,
     common_args: Args,
+    stats: RunStats,
 ) -> Result<()> {
     let test_env = TestEnv::new(
         args.num_iterations,
@@ -88,6 +67,7 @@ async fn spawn_ddos_jobs(
         client,
         common_args.seed,
         common_args.clone(),
+        stats,
     );
 
     if args.only_stats {
@@ -137,6 +117,7 @@ async fn print_stats(recievers: Vec<MsgAddressInt>, test_env: &TestEnv) {
             acc.failed += x.errors_count as u64;
             acc
         });
+    test_env.stats.add_confirmed(stats.success);
 
     for _ in 0..10 {
         log::info!(
@@ -159,13 +140,15 @@ async fn ddos_job(test_env: TestEnv, reciever: MsgAddressInt, payload_size: u32,
         test_env.rate_limiter.until_ready_with_jitter(jitter).await;
         let h = {
             let client = test_env.client.clone();
-            let counter = test_env.counter.clone();
+            let stats = test_env.stats.clone();
             tokio::spawn(async move {
-                if let Err(e) = client.broadcast_message(payload).await {
+                stats.record_built();
+                let start = Instant::now();
+                let result = archive::broadcast(&client, payload).await;
+                stats.record_result(start.elapsed(), &result);
+                if let Err(e) = result {
                     log::error!("Failed to send: {:?}", e);
-                    return;
                 }
-                counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
             })
         };
         if !test_env.args.no_wait {
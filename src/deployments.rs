@@ -0,0 +1,443 @@
+//! Resolution of deployed contracts under `<project_root>/deployments[/<network>]`.
+//!
+//! Every contract a subcommand needs is a [`Role`]. Roles listed in the manifest
+//! (`deployments.toml` in the deployments directory, or `--deployments-manifest`) are taken
+//! from it, the rest are found by deployment file names.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use everscale_rpc_client::RpcClient;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use ton_block::MsgAddressInt;
+
+use crate::models::GenericDeploymentInfo;
+use crate::Args;
+
+const MANIFEST_FILE: &str = "deployments.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// `WalletFactory`, derives sender wallets for `send`, `rand-send` and `send-to-targets`
+    WalletFactory,
+    /// `dudos-factory`, derives receivers for `dag`
+    DudosFactory,
+    /// EverWallet used by `latency`
+    SenderWallet,
+    /// DEX pools used by `swap`
+    DexPair,
+    /// Wallets used by `swap`
+    CommonAccount,
+}
+
+impl Role {
+    pub const ALL: [Role; 5] = [
+        Role::WalletFactory,
+        Role::DudosFactory,
+        Role::SenderWallet,
+        Role::DexPair,
+        Role::CommonAccount,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Role::WalletFactory => "wallet-factory",
+            Role::DudosFactory => "dudos-factory",
+            Role::SenderWallet => "sender-wallet",
+            Role::DexPair => "dex-pair",
+            Role::CommonAccount => "common-account",
+        }
+    }
+
+    /// Whether the role maps to a list of contracts rather than a single one.
+    pub fn is_multiple(self) -> bool {
+        matches!(self, Role::DexPair | Role::CommonAccount)
+    }
+
+    /// Lowercase file name parts a deployment file must all contain.
+    fn file_patterns(self) -> &'static [&'static str] {
+        match self {
+            Role::WalletFactory => &["factory", "wallet"],
+            Role::DudosFactory => &["factory", "dudos"],
+            Role::SenderWallet => &["ever-wallet"],
+            Role::DexPair => &["dexpair"],
+            Role::CommonAccount => &["commonaccount"],
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Role::ALL
+            .into_iter()
+            .find(|role| role.name() == s)
+            .with_context(|| {
+                let known: Vec<_> = Role::ALL.iter().map(|r| r.name()).collect();
+                format!("Unknown role `{s}`, expected one of: {}", known.join(", "))
+            })
+    }
+}
+
+/// Manifest layout, paths are relative to the deployments directory:
+///
+/// ```toml
+/// [roles]
+/// wallet-factory = "WalletFactory.json"
+/// sender-wallet = "0:1111111111111111111111111111111111111111111111111111111111111111"
+/// dex-pair = ["DexPair-WEVER-USDT.json", "DexPair-WEVER-QUBE.json"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    roles: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ManifestEntry {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Deployment {
+    pub role: Role,
+    pub address: MsgAddressInt,
+    /// Deployment file or `manifest` for addresses written in the manifest.
+    pub source: String,
+}
+
+pub struct Deployments {
+    dir: PathBuf,
+    manifest: Vec<(Role, Vec<String>)>,
+    files: Vec<PathBuf>,
+}
+
+impl Deployments {
+    pub fn load(args: &Args) -> Result<Self> {
+        Self::open(
+            &args.project_root,
+            args.network.as_deref(),
+            args.deployments_manifest.as_deref(),
+        )
+    }
+
+    pub fn open(
+        project_root: &Path,
+        network: Option<&str>,
+        manifest: Option<&Path>,
+    ) -> Result<Self> {
+        let mut dir = project_root.join("deployments");
+        if let Some(network) = network {
+            dir = dir.join(network);
+        }
+        if !dir.is_dir() {
+            anyhow::bail!("Deployment directory not found: {:?}", dir);
+        }
+        log::info!("Using deployments path: {:?}", dir);
+
+        let manifest_path = match manifest {
+            Some(path) => Some(path.to_path_buf()),
+            None => Some(dir.join(MANIFEST_FILE)).filter(|path| path.is_file()),
+        };
+        let manifest = match &manifest_path {
+            Some(path) => {
+                let data = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read manifest {path:?}"))?;
+                let manifest: Manifest = toml::from_str(&data)
+                    .with_context(|| format!("Failed to parse manifest {path:?}"))?;
+                log::info!("Using deployments manifest {:?}", path);
+                manifest
+            }
+            None => Manifest::default(),
+        };
+
+        let mut roles = Vec::new();
+        for (name, entry) in manifest.roles {
+            let role: Role = name.parse()?;
+            let values = match entry {
+                ManifestEntry::One(value) => vec![value],
+                ManifestEntry::Many(values) => values,
+            };
+            if !role.is_multiple() && values.len() != 1 {
+                anyhow::bail!(
+                    "Role `{}` takes a single deployment, manifest lists {}",
+                    role.name(),
+                    values.len()
+                );
+            }
+            roles.push((role, values));
+        }
+
+        // Only the selected directory, network subdirectories hold other networks' contracts.
+        let mut files: Vec<_> = walkdir::WalkDir::new(&dir)
+            .max_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter(|e| e.path().extension().map(|e| e == "json").unwrap_or(false))
+            .map(|e| e.into_path())
+            .collect();
+        files.sort();
+
+        Ok(Self {
+            dir,
+            manifest: roles,
+            files,
+        })
+    }
+
+    /// Resolves a single-contract role, failing when it is missing or ambiguous.
+    pub fn one(&self, role: Role) -> Result<Deployment> {
+        self.resolve(role)?.into_iter().next().with_context(|| {
+            format!(
+                "No deployment found for role `{}` in {:?}, map it in {}",
+                role.name(),
+                self.dir,
+                MANIFEST_FILE
+            )
+        })
+    }
+
+    /// Resolves every contract of the role, the result may be empty.
+    pub fn all(&self, role: Role) -> Result<Vec<Deployment>> {
+        self.resolve(role)
+    }
+
+    fn resolve(&self, role: Role) -> Result<Vec<Deployment>> {
+        if let Some((_, values)) = self.manifest.iter().find(|(r, _)| *r == role) {
+            return values
+                .iter()
+                .map(|value| match MsgAddressInt::from_str(value) {
+                    Ok(address) => Ok(Deployment {
+                        role,
+                        address,
+                        source: "manifest".to_string(),
+                    }),
+                    Err(_) => read_deployment(role, &self.dir.join(value)),
+                })
+                .collect();
+        }
+
+        let patterns = role.file_patterns();
+        let matches: Vec<_> = self
+            .files
+            .iter()
+            .filter(|path| {
+                let name = file_name(path);
+                patterns.iter().all(|pattern| name.contains(pattern))
+            })
+            .collect();
+
+        if !role.is_multiple() && matches.len() > 1 {
+            anyhow::bail!(
+                "Role `{}` is ambiguous, candidates: {:?}. Pick one in {}",
+                role.name(),
+                matches,
+                MANIFEST_FILE
+            );
+        }
+
+        matches
+            .into_iter()
+            .map(|path| read_deployment(role, path))
+            .collect()
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn read_deployment(role: Role, path: &Path) -> Result<Deployment> {
+    let data =
+        std::fs::read(path).with_context(|| format!("Failed to read deployment {path:?}"))?;
+    let info: GenericDeploymentInfo = serde_json::from_slice(&data)
+        .with_context(|| format!("Invalid deployment file {path:?}"))?;
+    Ok(Deployment {
+        role,
+        address: info.address,
+        source: path.display().to_string(),
+    })
+}
+
+#[derive(Parser, Debug, Clone, Serialize)]
+pub struct DeploymentsArgs {
+    #[command(subcommand)]
+    command: DeploymentsCommand,
+}
+
+#[derive(Subcommand, Debug, Clone, Serialize)]
+pub enum DeploymentsCommand {
+    /// List what every role resolves to and check that the contracts exist on chain
+    Check,
+}
+
+pub(crate) async fn run(args: DeploymentsArgs, common_args: Args, client: RpcClient) -> Result<()> {
+    match args.command {
+        DeploymentsCommand::Check => check(&common_args, &client).await,
+    }
+}
+
+async fn check(common_args: &Args, client: &RpcClient) -> Result<()> {
+    let deployments = Deployments::load(common_args)?;
+
+    let mut resolved = Vec::new();
+    let mut problems = 0;
+    for role in Role::ALL {
+        match deployments.all(role) {
+            Ok(found) if found.is_empty() => log::info!("{:<16} not configured", role.name()),
+            Ok(found) => resolved.extend(found),
+            Err(e) => {
+                log::error!("{:<16} {:#}", role.name(), e);
+                problems += 1;
+            }
+        }
+    }
+
+    let states: Vec<_> = futures_util::stream::iter(&resolved)
+        .map(|deployment| client.get_contract_state(&deployment.address, None))
+        .buffered(100)
+        .collect()
+        .await;
+
+    for (deployment, state) in resolved.iter().zip(states) {
+        let status = match state {
+            Ok(Some(state)) => format!(
+                "ok, balance {}",
+                state.account.storage.balance.grams.as_u128()
+            ),
+            Ok(None) => {
+                problems += 1;
+                "MISSING on chain".to_string()
+            }
+            Err(e) => {
+                problems += 1;
+                format!("failed to fetch state: {e}")
+            }
+        };
+        log::info!(
+            "{:<16} {} ({}): {}",
+            deployment.role.name(),
+            deployment.address,
+            deployment.source,
+            status
+        );
+    }
+
+    if problems > 0 {
+        anyhow::bail!("{problems} deployment problem(s) found");
+    }
+    log::info!("All {} deployments are present", resolved.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_deployment(dir: &Path, name: &str, index: u32) {
+        std::fs::write(
+            dir.join(name),
+            format!("{{\"address\": \"0:{index:064x}\"}}"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_resolve_by_file_names() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("deployments").join("testnet");
+        std::fs::create_dir_all(&dir).unwrap();
+        write_deployment(&dir, "WalletFactory.json", 1);
+        write_deployment(&dir, "dudos-factory.json", 2);
+        write_deployment(&dir, "DexPair-A.json", 3);
+        write_deployment(&dir, "DexPair-B.json", 4);
+
+        let deployments = Deployments::open(root.path(), Some("testnet"), None).unwrap();
+        let address = |i: u32| MsgAddressInt::from_str(&format!("0:{i:064x}")).unwrap();
+        assert_eq!(
+            deployments.one(Role::WalletFactory).unwrap().address,
+            address(1)
+        );
+        assert_eq!(
+            deployments.one(Role::DudosFactory).unwrap().address,
+            address(2)
+        );
+        assert_eq!(deployments.all(Role::DexPair).unwrap().len(), 2);
+        assert!(deployments.one(Role::SenderWallet).is_err());
+        assert!(deployments.all(Role::CommonAccount).unwrap().is_empty());
+
+        assert!(Deployments::open(root.path(), Some("mainnet"), None).is_err());
+    }
+
+    #[test]
+    fn test_factory_roles_need_their_name() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("deployments");
+        std::fs::create_dir_all(&dir).unwrap();
+        write_deployment(&dir, "Factory.json", 1);
+
+        let deployments = Deployments::open(root.path(), None, None).unwrap();
+        assert!(deployments.one(Role::WalletFactory).is_err());
+        assert!(deployments.one(Role::DudosFactory).is_err());
+
+        write_deployment(&dir, "WalletFactory-old.json", 2);
+        write_deployment(&dir, "WalletFactory-new.json", 3);
+        let deployments = Deployments::open(root.path(), None, None).unwrap();
+        assert!(deployments.one(Role::WalletFactory).is_err());
+    }
+
+    #[test]
+    fn test_manifest_overrides_ambiguity() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("deployments");
+        std::fs::create_dir_all(&dir).unwrap();
+        write_deployment(&dir, "ever-wallet-1.json", 1);
+        write_deployment(&dir, "ever-wallet-2.json", 2);
+
+        let deployments = Deployments::open(root.path(), None, None).unwrap();
+        assert!(deployments.one(Role::SenderWallet).is_err());
+
+        std::fs::write(
+            dir.join(MANIFEST_FILE),
+            "[roles]\nsender-wallet = \"ever-wallet-2.json\"\n",
+        )
+        .unwrap();
+        let deployments = Deployments::open(root.path(), None, None).unwrap();
+        assert_eq!(
+            deployments.one(Role::SenderWallet).unwrap().address,
+            MsgAddressInt::from_str(&format!("0:{:064x}", 2)).unwrap()
+        );
+
+        std::fs::write(dir.join(MANIFEST_FILE), "[roles]\nsender = \"x.json\"\n").unwrap();
+        assert!(Deployments::open(root.path(), None, None).is_err());
+    }
+
+    #[test]
+    fn test_network_directories_stay_separate() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("deployments");
+        for (network, index) in [("testnet", 1), ("mainnet", 2)] {
+            std::fs::create_dir_all(dir.join(network)).unwrap();
+            write_deployment(&dir.join(network), "WalletFactory.json", index);
+        }
+
+        let deployments = Deployments::open(root.path(), None, None).unwrap();
+        assert!(deployments.one(Role::WalletFactory).is_err());
+
+        let deployments = Deployments::open(root.path(), Some("mainnet"), None).unwrap();
+        assert_eq!(
+            deployments.one(Role::WalletFactory).unwrap().address,
+            MsgAddressInt::from_str(&format!("0:{:064x}", 2)).unwrap()
+        );
+    }
+}
@@ -1,24 +1,43 @@
+use crate::endpoints::{self, ErrorClass};
+use crate::report::{MethodStats, RunStats};
 use crate::Args;
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use everscale_rpc_client::RpcClient;
 use governor::{Jitter, RateLimiter};
-use histogram::{AtomicHistogram, Histogram};
+use histogram::Histogram;
+use rand::distributions::WeightedIndex;
 use rand::prelude::*;
+use serde::Serialize;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
-use ton_block::MsgAddressInt;
+use ton_block::{ExternalInboundMessageHeader, Message, MsgAddressInt};
+use ton_types::{BuilderData, SliceData};
 
-#[derive(Parser, Debug, Clone)]
+/// Prefix of the `--accounts-file` lines naming the code hashes the list was fetched for.
+const CODE_HASH_HEADER: &str = "# code hash ";
+
+#[derive(Parser, Debug, Clone, Serialize)]
 pub struct DosTestArgs {
+    #[clap(long, value_delimiter = ',', required_unless_present = "accounts_file")]
+    /// Code hashes as hex strings, accounts of all of them are merged
+    code_hash: Vec<String>,
+
     #[clap(long)]
-    /// Code hash as hex string
-    code_hash: String,
+    /// Account list to load instead of paginating by code hash, it must have been fetched for the
+    /// same `--code-hash` when one is given. Written after pagination when it doesn't exist yet
+    accounts_file: Option<PathBuf>,
+
+    #[clap(long, requires_all = ["accounts_file", "code_hash"])]
+    /// Paginate by code hash even though `--accounts-file` exists and overwrite it
+    refresh_accounts: bool,
 
     #[clap(short, long, default_value = "10")]
-    /// Requests per second for random account fetches
+    /// Requests per second across the whole mix
     rps: u32,
 
     #[clap(short, long, default_value = "60")]
@@ -28,47 +47,105 @@ pub struct DosTestArgs {
     #[clap(long, default_value = "100")]
     /// Maximum concurrent requests
     max_concurrent: usize,
+
+    #[clap(long, value_delimiter = ',', default_value = "contract-state=1")]
+    /// Weighted request mix as `<method>=<weight>` pairs, e.g.
+    /// `contract-state=70,transactions=15,config=5,accounts-by-code-hash=5,send-message=5`
+    mix: Vec<MixEntry>,
+}
+
+/// Requests of the mix, all but `config` and `send-message` target a random account found by
+/// the code hash.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum RpcMethod {
+    ContractState,
+    /// Latest transactions of the account
+    Transactions,
+    Config,
+    /// Page of accounts with the code hash, continuing from the account
+    AccountsByCodeHash,
+    /// Unsigned external message with an empty body to the account, no contract accepts it
+    SendMessage,
+}
+
+impl RpcMethod {
+    /// jrpc method name, labels the per-method stats.
+    fn name(self) -> &'static str {
+        match self {
+            RpcMethod::ContractState => "getContractState",
+            RpcMethod::Transactions => "getTransactionsList",
+            RpcMethod::Config => "getBlockchainConfig",
+            RpcMethod::AccountsByCodeHash => "getAccountsByCodeHash",
+            RpcMethod::SendMessage => "sendMessage",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct MixEntry {
+    method: RpcMethod,
+    weight: u32,
+}
+
+impl FromStr for MixEntry {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (method, weight) = s
+            .split_once('=')
+            .with_context(|| format!("Expected `<method>=<weight>`, got `{s}`"))?;
+        Ok(Self {
+            method: RpcMethod::from_str(method.trim(), true).map_err(|e| anyhow::anyhow!(e))?,
+            weight: weight
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid weight of `{method}`"))?,
+        })
+    }
 }
 
 pub(crate) async fn run(
     dos_args: DosTestArgs,
     _common_args: Args,
     client: RpcClient,
+    stats: RunStats,
 ) -> Result<()> {
-    let code_hash_bytes = hex::decode(&dos_args.code_hash).context("Failed to decode code hash")?;
-    let code_hash: [u8; 32] = code_hash_bytes
-        .try_into()
-        .map_err(|_| anyhow::anyhow!("Code hash must be exactly 32 bytes (64 hex chars)"))?;
-
-    log::info!("Fetching accounts with code hash: {}", dos_args.code_hash);
-
-    let mut all_accounts = Vec::new();
-    let mut continuation: Option<MsgAddressInt> = None;
-
-    loop {
-        let batch = client
-            .get_accounts_by_code_hash(code_hash, continuation.as_ref(), 100)
-            .await
-            .context("Failed to fetch accounts by code hash")?;
+    let code_hashes = dos_args
+        .code_hash
+        .iter()
+        .map(|hash| parse_code_hash(hash))
+        .collect::<Result<Vec<_>>>()?;
+    if code_hashes.is_empty()
+        && dos_args
+            .mix
+            .iter()
+            .any(|entry| entry.method == RpcMethod::AccountsByCodeHash)
+    {
+        anyhow::bail!("`accounts-by-code-hash` requests need --code-hash");
+    }
 
-        if batch.is_empty() {
-            break;
+    let all_accounts = match &dos_args.accounts_file {
+        Some(path) if path.exists() && !dos_args.refresh_accounts => {
+            let (file_hashes, accounts) = read_accounts_file(path)?;
+            if !code_hashes.is_empty() && !same_code_hashes(&file_hashes, &dos_args.code_hash) {
+                anyhow::bail!(
+                    "{path:?} lists accounts of code hashes {file_hashes:?}, pass \
+                    --refresh-accounts to fetch the ones of --code-hash"
+                );
+            }
+            log::info!("Loaded {} accounts from {:?}", accounts.len(), path);
+            accounts
         }
-
-        let batch_size = batch.len();
-        continuation = batch.last().cloned();
-        all_accounts.extend(batch);
-
-        log::info!(
-            "Fetched {} accounts (total: {})",
-            batch_size,
-            all_accounts.len()
-        );
-
-        if batch_size < 100 {
-            break;
+        path => {
+            let accounts = fetch_accounts(&client, &dos_args.code_hash, &code_hashes).await?;
+            if let Some(path) = path.as_ref().filter(|_| !accounts.is_empty()) {
+                write_accounts_file(path, &dos_args.code_hash, &accounts)?;
+                log::info!("Saved {} accounts to {:?}", accounts.len(), path);
+            }
+            accounts
         }
-    }
+    };
 
     if all_accounts.is_empty() {
         return Err(anyhow::anyhow!(
@@ -79,21 +156,24 @@ pub(crate) async fn run(
     log::info!("Total accounts found: {}", all_accounts.len());
     let all_accounts = Arc::new(all_accounts);
 
+    let weights = WeightedIndex::new(dos_args.mix.iter().map(|entry| entry.weight))
+        .context("Invalid --mix weights")?;
+    let methods: Vec<(RpcMethod, Arc<MethodStats>)> = dos_args
+        .mix
+        .iter()
+        .map(|entry| (entry.method, stats.method(entry.method.name())))
+        .collect();
+
     let rate_limiter = Arc::new(RateLimiter::direct(
         governor::Quota::per_second(std::num::NonZeroU32::new(dos_args.rps).unwrap())
             .allow_burst(std::num::NonZeroU32::new(dos_args.rps.max(10)).unwrap()),
     ));
     let jitter = Jitter::new(Duration::ZERO, Duration::from_millis(100));
+    stats.set_target_rps(dos_args.rps as u64);
 
-    const MAX_VALUE_POWER: u8 = 34;
-    const GROUPING_POWER: u8 = 10;
-    let histogram = Arc::new(
-        AtomicHistogram::new(GROUPING_POWER, MAX_VALUE_POWER)
-            .context("Failed to create histogram")?,
-    );
-
-    let success = Arc::new(std::sync::atomic::AtomicU64::new(0));
-    let failed = Arc::new(std::sync::atomic::AtomicU64::new(0));
+    let histogram = stats.latency.clone();
+    let success = stats.sent.clone();
+    let failed = stats.failed.clone();
 
     let semaphore = Arc::new(tokio::sync::Semaphore::new(dos_args.max_concurrent));
 
@@ -141,7 +221,10 @@ pub(crate) async fn run(
 
         let address_idx = rng.gen_range(0..all_accounts.len());
         let address = all_accounts[address_idx].clone();
+        let (method, method_stats) = methods[weights.sample(&mut rng)].clone();
+        let code_hash = code_hashes.choose(&mut rng).copied().unwrap_or_default();
 
+        stats.record_built();
         let client = client.clone();
         let histogram = histogram.clone();
         let success = success.clone();
@@ -153,8 +236,13 @@ pub(crate) async fn run(
             let _task_guard = task_tx;
 
             let start = Instant::now();
-            let result = client.get_contract_state(&address, None).await;
-            let elapsed = start.elapsed().as_nanos() as u64;
+            let address = &address;
+            let result = endpoints::call(&client, |client| async move {
+                request(&client, method, address, code_hash).await
+            })
+            .await;
+            let elapsed = start.elapsed();
+            method_stats.record(elapsed, result.is_ok());
 
             match result {
                 Ok(_) => {
@@ -162,11 +250,11 @@ pub(crate) async fn run(
                 }
                 Err(e) => {
                     failed.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                    log::debug!("Request failed: {}", e);
+                    log::debug!("{} failed ({}): {:?}", method.name(), ErrorClass::of(&e), e);
                 }
             }
 
-            let _ = histogram.increment(elapsed);
+            let _ = histogram.increment(elapsed.as_nanos() as u64);
         });
 
         spawned_count += 1;
@@ -217,6 +305,158 @@ pub(crate) async fn run(
     let final_snapshot = histogram.load();
     print_histogram(&final_snapshot);
 
+    if methods.len() > 1 {
+        for (name, method) in stats.methods() {
+            log::info!(
+                "\n=== {}: {} ok, {} failed ===",
+                name,
+                method.ok.load(std::sync::atomic::Ordering::Relaxed),
+                method.failed.load(std::sync::atomic::Ordering::Relaxed)
+            );
+            print_histogram(&method.latency.load());
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_code_hash(hash: &str) -> Result<[u8; 32]> {
+    let code_hash_bytes = hex::decode(hash).context("Failed to decode code hash")?;
+    code_hash_bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Code hash must be exactly 32 bytes (64 hex chars)"))
+}
+
+/// Paginates every code hash, the merged list is sorted and free of duplicates.
+async fn fetch_accounts(
+    client: &RpcClient,
+    hashes_hex: &[String],
+    code_hashes: &[[u8; 32]],
+) -> Result<Vec<MsgAddressInt>> {
+    let mut all_accounts = Vec::new();
+
+    for (hash_hex, code_hash) in hashes_hex.iter().zip(code_hashes) {
+        log::info!("Fetching accounts with code hash: {}", hash_hex);
+        let mut continuation: Option<MsgAddressInt> = None;
+
+        loop {
+            let batch = client
+                .get_accounts_by_code_hash(*code_hash, continuation.as_ref(), 100)
+                .await
+                .context("Failed to fetch accounts by code hash")?;
+
+            if batch.is_empty() {
+                break;
+            }
+
+            let batch_size = batch.len();
+            continuation = batch.last().cloned();
+            all_accounts.extend(batch);
+
+            log::info!(
+                "Fetched {} accounts (total: {})",
+                batch_size,
+                all_accounts.len()
+            );
+
+            if batch_size < 100 {
+                break;
+            }
+        }
+    }
+
+    all_accounts.sort();
+    all_accounts.dedup();
+    Ok(all_accounts)
+}
+
+/// Reads one address per line, `#` starts a comment. Returns the code hashes of the
+/// `# code hash` header lines along with the accounts.
+fn read_accounts_file(path: &Path) -> Result<(Vec<String>, Vec<MsgAddressInt>)> {
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read accounts file {path:?}"))?;
+
+    let mut code_hashes = Vec::new();
+    let mut accounts = Vec::new();
+    for (line_no, line) in data.lines().enumerate() {
+        if let Some(code_hash) = line.strip_prefix(CODE_HASH_HEADER) {
+            code_hashes.push(code_hash.trim().to_string());
+            continue;
+        }
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let address = MsgAddressInt::from_str(line)
+            .map_err(|e| anyhow::anyhow!("{path:?}:{}: invalid address: {e}", line_no + 1))?;
+        accounts.push(address);
+    }
+    Ok((code_hashes, accounts))
+}
+
+fn same_code_hashes(a: &[String], b: &[String]) -> bool {
+    let normalize = |hashes: &[String]| {
+        let mut hashes: Vec<_> = hashes.iter().map(|h| h.to_lowercase()).collect();
+        hashes.sort();
+        hashes.dedup();
+        hashes
+    };
+    normalize(a) == normalize(b)
+}
+
+fn write_accounts_file(
+    path: &Path,
+    code_hashes: &[String],
+    accounts: &[MsgAddressInt],
+) -> Result<()> {
+    let mut data = String::new();
+    for code_hash in code_hashes {
+        data.push_str(&format!("{CODE_HASH_HEADER}{code_hash}\n"));
+    }
+    for address in accounts {
+        data.push_str(&format!("{address}\n"));
+    }
+
+    // An interrupted write must not leave a truncated list to be loaded by the next run.
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    std::fs::write(&tmp, data).with_context(|| format!("Failed to write accounts file {tmp:?}"))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace accounts file {path:?}"))
+}
+
+async fn request(
+    client: &RpcClient,
+    method: RpcMethod,
+    address: &MsgAddressInt,
+    code_hash: [u8; 32],
+) -> Result<()> {
+    match method {
+        RpcMethod::ContractState => {
+            client.get_contract_state(address, None).await?;
+        }
+        RpcMethod::Transactions => {
+            client.get_transactions(10, address, None).await?;
+        }
+        RpcMethod::Config => {
+            client.get_blockchain_config().await?;
+        }
+        RpcMethod::AccountsByCodeHash => {
+            client
+                .get_accounts_by_code_hash(code_hash, Some(address), 100)
+                .await?;
+        }
+        RpcMethod::SendMessage => {
+            let message = Message::with_ext_in_header_and_body(
+                ExternalInboundMessageHeader {
+                    dst: address.clone(),
+                    ..Default::default()
+                },
+                SliceData::load_builder(BuilderData::new())?,
+            );
+            client.broadcast_message(message).await?;
+        }
+    }
     Ok(())
 }
 
@@ -0,0 +1,338 @@
+//! Per-endpoint request attribution, enabled with `--endpoint-stats` or `--metrics-addr`.
+//!
+//! The shared client balances requests over the endpoints by itself and can't tell which one
+//! served a request. With the flag every endpoint gets a client of its own, and the requests on
+//! the load paths (broadcasts, `accounts-dos` requests, sender state reads, latency and tracker
+//! lookups) go through [`call`], which picks the endpoints round-robin, times every request and
+//! classifies its failure. One-off setup requests like deployment checks stay on the shared
+//! client.
+//!
+//! Endpoints unreachable at start are left out. One that times out or fails to connect is skipped
+//! for [`UNAVAILABLE_COOLDOWN`], and requests fall back to the shared client while all are.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use everscale_rpc_client::RpcClient;
+use histogram::AtomicHistogram;
+use serde::Serialize;
+use url::Url;
+
+use crate::report::{latency_histogram, LatencyReport};
+
+/// How long an endpoint is skipped after it timed out or failed to connect or serve.
+const UNAVAILABLE_COOLDOWN: Duration = Duration::from_secs(5);
+
+struct Endpoint {
+    url: Url,
+    client: RpcClient,
+    ok: AtomicU64,
+    errors: Mutex<BTreeMap<ErrorClass, u64>>,
+    latency: AtomicHistogram,
+    unavailable_until: Mutex<Option<Instant>>,
+}
+
+impl Endpoint {
+    fn record(&self, elapsed: Duration, error: Option<&anyhow::Error>) {
+        match error {
+            None => {
+                self.ok.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(e) => {
+                let class = ErrorClass::of(e);
+                log::debug!("Request to {} failed ({class}): {e:?}", self.url);
+                *self.errors.lock().unwrap().entry(class).or_default() += 1;
+                if class.is_unavailable() {
+                    *self.unavailable_until.lock().unwrap() =
+                        Some(Instant::now() + UNAVAILABLE_COOLDOWN);
+                }
+            }
+        }
+        let _ = self.latency.increment(elapsed.as_nanos() as u64);
+    }
+
+    fn is_available(&self) -> bool {
+        match *self.unavailable_until.lock().unwrap() {
+            Some(until) => Instant::now() >= until,
+            None => true,
+        }
+    }
+}
+
+struct Pool {
+    endpoints: Vec<Endpoint>,
+    next: AtomicUsize,
+}
+
+/// Endpoints of the current run, replaced by every [`start`].
+static POOL: Mutex<Option<Arc<Pool>>> = Mutex::new(None);
+
+/// Kind of a failed request, as far as it can be told from the error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorClass {
+    /// The node refused the external message.
+    Rejected,
+    Timeout,
+    /// Connection, DNS or TLS failure.
+    Transport,
+    HttpStatus(u16),
+    /// JSON-RPC error with its code.
+    Rpc(i64),
+    Other,
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rejected => f.write_str("rejected"),
+            Self::Timeout => f.write_str("timeout"),
+            Self::Transport => f.write_str("transport"),
+            Self::HttpStatus(status) => write!(f, "http_{status}"),
+            Self::Rpc(code) => write!(f, "rpc_{code}"),
+            Self::Other => f.write_str("other"),
+        }
+    }
+}
+
+impl ErrorClass {
+    /// The endpoint itself is down or overloaded, not just the request refused.
+    fn is_unavailable(self) -> bool {
+        match self {
+            Self::Timeout | Self::Transport => true,
+            Self::HttpStatus(status) => status >= 500,
+            Self::Rejected | Self::Rpc(_) | Self::Other => false,
+        }
+    }
+
+    /// HTTP and socket failures are told by the `reqwest`, `tokio` and `std::io` errors of the
+    /// chain. The client reports node and JSON-RPC errors as text only, so rejections and
+    /// JSON-RPC codes are read from the message.
+    pub fn of(error: &anyhow::Error) -> Self {
+        for cause in error.chain() {
+            if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                if e.is_timeout() {
+                    return Self::Timeout;
+                }
+                if let Some(status) = e.status() {
+                    return Self::HttpStatus(status.as_u16());
+                }
+                if e.is_connect() || e.is_request() || e.is_body() {
+                    return Self::Transport;
+                }
+            } else if cause.is::<tokio::time::error::Elapsed>() {
+                return Self::Timeout;
+            } else if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+                return match e.kind() {
+                    std::io::ErrorKind::TimedOut => Self::Timeout,
+                    _ => Self::Transport,
+                };
+            }
+        }
+
+        let text = format!("{error:#}").to_lowercase();
+        if text.contains("rejected") || text.contains("not accepted") {
+            Self::Rejected
+        } else if let Some(code) = number_after(&text, "code", |c| " :=\"'".contains(c)) {
+            Self::Rpc(code)
+        } else {
+            Self::Other
+        }
+    }
+}
+
+/// Integer following `keyword` after nothing but characters accepted by `skip`, e.g. `-32602` of
+/// `"code": -32602`.
+fn number_after(text: &str, keyword: &str, skip: fn(char) -> bool) -> Option<i64> {
+    text.match_indices(keyword).find_map(|(at, _)| {
+        let rest = text[at + keyword.len()..].trim_start_matches(skip);
+        let digits = rest.strip_prefix('-').unwrap_or(rest);
+        let len = digits
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(digits.len());
+        if len == 0 {
+            return None;
+        }
+        rest[..rest.len() - digits.len() + len].parse().ok()
+    })
+}
+
+/// Switches [`call`] to per-endpoint clients for the run, or back to the shared client when
+/// `clients` is empty. Counts of a previous run are dropped.
+pub fn start(clients: Vec<(Url, RpcClient)>) {
+    let pool = Pool {
+        endpoints: clients
+            .into_iter()
+            .map(|(url, client)| Endpoint {
+                url,
+                client,
+                ok: AtomicU64::new(0),
+                errors: Mutex::new(BTreeMap::new()),
+                latency: latency_histogram(),
+                unavailable_until: Mutex::new(None),
+            })
+            .collect(),
+        next: AtomicUsize::new(0),
+    };
+    let pool = (!pool.endpoints.is_empty()).then(|| Arc::new(pool));
+    if pool.is_some() {
+        log::info!("Attributing requests to endpoints");
+    }
+    *POOL.lock().unwrap() = pool;
+}
+
+fn current() -> Option<Arc<Pool>> {
+    POOL.lock().unwrap().clone()
+}
+
+/// Runs the request on the next available endpoint and records its outcome. Runs it on `client`,
+/// which only picks live endpoints, unless started or while every endpoint is unavailable.
+pub async fn call<T, F, Fut>(client: &RpcClient, request: F) -> Result<T>
+where
+    F: FnOnce(RpcClient) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let Some(pool) = current() else {
+        return request(client.clone()).await;
+    };
+    let first = pool.next.fetch_add(1, Ordering::Relaxed);
+    let len = pool.endpoints.len();
+    let available = (0..len)
+        .map(|i| &pool.endpoints[(first + i) % len])
+        .find(|endpoint| endpoint.is_available());
+    let Some(endpoint) = available else {
+        return request(client.clone()).await;
+    };
+
+    let start = Instant::now();
+    let result = request(endpoint.client.clone()).await;
+    endpoint.record(start.elapsed(), result.as_ref().err());
+    result
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointReport {
+    pub ok: u64,
+    pub failed: u64,
+    /// Failed requests by [`ErrorClass`].
+    pub errors: BTreeMap<String, u64>,
+    /// Time of successful and failed requests.
+    pub latency: Option<LatencyReport>,
+}
+
+/// Requests of every endpoint in the current run so far, empty unless started.
+pub fn report() -> BTreeMap<String, EndpointReport> {
+    let Some(pool) = current() else {
+        return BTreeMap::new();
+    };
+    pool.endpoints
+        .iter()
+        .map(|endpoint| {
+            let errors: BTreeMap<_, _> = endpoint
+                .errors
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(class, count)| (class.to_string(), *count))
+                .collect();
+            let report = EndpointReport {
+                ok: endpoint.ok.load(Ordering::Relaxed),
+                failed: errors.values().sum(),
+                errors,
+                latency: LatencyReport::from_histogram(&endpoint.latency.load()),
+            };
+            (endpoint.url.to_string(), report)
+        })
+        .collect()
+}
+
+pub fn print_table(endpoints: &BTreeMap<String, EndpointReport>) {
+    if endpoints.is_empty() {
+        return;
+    }
+    let url_width = endpoints
+        .keys()
+        .map(String::len)
+        .max()
+        .unwrap_or_default()
+        .max("ENDPOINT".len());
+    let ms = |latency: &Option<LatencyReport>, ns: fn(&LatencyReport) -> u64| {
+        latency
+            .as_ref()
+            .map(|l| format!("{:.1}", ns(l) as f64 / 1e6))
+            .unwrap_or_else(|| "-".to_string())
+    };
+
+    log::info!("Requests by endpoint:");
+    log::info!(
+        "  {:url_width$}  {:>8}  {:>8}  {:>9}  {:>9}  {:>9}  ERRORS",
+        "ENDPOINT",
+        "OK",
+        "FAILED",
+        "P50 MS",
+        "P95 MS",
+        "P99 MS"
+    );
+    for (url, endpoint) in endpoints {
+        let errors: Vec<_> = endpoint
+            .errors
+            .iter()
+            .map(|(class, count)| format!("{class}={count}"))
+            .collect();
+        log::info!(
+            "  {url:url_width$}  {:>8}  {:>8}  {:>9}  {:>9}  {:>9}  {}",
+            endpoint.ok,
+            endpoint.failed,
+            ms(&endpoint.latency, |l| l.p50_ns),
+            ms(&endpoint.latency, |l| l.p95_ns),
+            ms(&endpoint.latency, |l| l.p99_ns),
+            errors.join(" ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_classify_errors() {
+        let class = |message: &str| ErrorClass::of(&anyhow::anyhow!("{message}"));
+        assert_eq!(
+            class("Failed to send message: message rejected: cannot deploy"),
+            ErrorClass::Rejected
+        );
+        assert_eq!(
+            class(r#"JrpcError { code: -32602, message: "invalid params" }"#),
+            ErrorClass::Rpc(-32602)
+        );
+        assert_eq!(class("no code hash"), ErrorClass::Other);
+        assert_eq!(ErrorClass::Rpc(-32602).to_string(), "rpc_-32602");
+
+        // Typed causes win over the text of the outer context.
+        let closed = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let refused = reqwest::Client::new()
+            .post(format!("http://{closed}/rpc"))
+            .send()
+            .await
+            .unwrap_err();
+        let refused = anyhow::Error::new(refused).context("JSON-RPC code 7 request failed");
+        assert_eq!(ErrorClass::of(&refused), ErrorClass::Transport);
+
+        let elapsed = tokio::time::timeout(Duration::ZERO, std::future::pending::<()>())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            ErrorClass::of(&anyhow::Error::new(elapsed)),
+            ErrorClass::Timeout
+        );
+    }
+}
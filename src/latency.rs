@@ -1,19 +1,33 @@
 pub mod combined_plot;
 pub mod plotting;
-
-use crate::models::GenericDeploymentInfo;
-use crate::{send, Args};
+pub mod samples;
+
+use crate::endpoints;
+use crate::preflight::{self, SenderPlan};
+use crate::report::RunStats;
+use crate::send::{self, Transfer};
+use crate::wallets::{self, Sender, WalletKind};
+use crate::Args;
 use anyhow::{Context, Result};
 use clap::Parser;
 use ed25519_dalek::Keypair;
 use everscale_rpc_client::RpcClient;
 use governor::RateLimiter;
+use nekoton_abi::GenTimings;
+use serde::Serialize;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::{Duration, Instant, SystemTime};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
+use ton_block::{AccountStuff, MsgAddressInt};
+
+const CONFIRMATION_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long the masterchain logical time may take to pass a found transaction.
+const MC_LT_TIMEOUT: Duration = Duration::from_secs(60);
 
-#[derive(Parser, Debug, Clone)]
+#[derive(Parser, Debug, Clone, Serialize)]
 pub struct LatencyTestArgs {
     #[clap(short, long)]
     /// Number of transactions to send
@@ -41,203 +55,446 @@ pub struct LatencyTestArgs {
     #[clap(long)]
     /// Time window in minutes for time series plots (auto-calculated if not specified)
     time_window: Option<u64>,
+
+    #[clap(long, default_value = "1")]
+    /// Sender wallets used round-robin, more than one are taken like in `send`
+    senders: u32,
+
+    #[clap(long, default_value = "1000")]
+    /// Transactions awaiting confirmation at once
+    max_in_flight: usize,
+
+    #[clap(long)]
+    /// Embed the scripts and styles into the HTML report, it then opens without internet access
+    embed_assets: bool,
+}
+
+#[derive(Parser, Debug, Clone, Serialize)]
+pub struct PlotArgs {
+    #[clap(required = true)]
+    /// CSV files written by `latency --csv`, their samples are merged
+    csv: Vec<PathBuf>,
+
+    #[clap(short, long)]
+    /// Path of the HTML report
+    output: PathBuf,
+
+    #[clap(long)]
+    /// SLA threshold for marking violations
+    sla_threshold: Option<u64>,
+
+    #[clap(long)]
+    /// Time window in minutes for time series plots (auto-calculated if not specified)
+    time_window: Option<u64>,
+
+    #[clap(long)]
+    /// Overlay every CSV as a separate run instead of merging them, the first one is the baseline
+    compare: bool,
+
+    #[clap(long, requires = "compare")]
+    /// Labels of the compared runs in CSV order, file names by default
+    label: Vec<String>,
+
+    #[clap(long)]
+    /// Embed the scripts and styles into the HTML report, it then opens without internet access
+    embed_assets: bool,
 }
 
 pub(crate) async fn run(
     latency_args: LatencyTestArgs,
     common_args: Args,
-    keypair: &Keypair,
+    keypair: Arc<Keypair>,
     client: RpcClient,
+    stats: RunStats,
 ) -> Result<()> {
-    const COST_PER_TRANSACTION: u64 = 8_857_001;
-
-    let base_deployments_path = common_args.project_root.join("deployments");
-    let network_deployments_path = if let Some(network_name) = &common_args.network {
-        base_deployments_path.join(network_name)
-    } else {
-        base_deployments_path
+    let senders = match latency_args.senders {
+        0 => anyhow::bail!("At least one sender is required"),
+        1 => vec![wallets::main_sender(&common_args, &keypair)?],
+        count => wallets::load_senders(&common_args, &client, &keypair, count).await?,
     };
-
-    if common_args.network.is_some() && !network_deployments_path.is_dir() {
-        return Err(anyhow::anyhow!(
-            "Specified network deployment directory not found: {:?}",
-            network_deployments_path
-        ));
+    for sender in &senders {
+        log::info!("Sender address: {}", sender.address);
     }
 
-    log::info!("Using deployments path: {:?}", network_deployments_path);
-
-    let wallet = walkdir::WalkDir::new(&network_deployments_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| e.path().extension().map(|e| e == "json").unwrap_or(false))
-        .find(|e| {
-            e.path()
-                .file_name()
-                .and_then(|n| n.to_str())
-                .map(|n| n.to_lowercase().contains("ever-wallet"))
-                .unwrap_or(false)
+    let per_sender = latency_args.num_txs.div_ceil(senders.len()) as u64;
+    let plan: Vec<_> = senders
+        .iter()
+        .map(|sender| SenderPlan {
+            wallet: sender.address.clone(),
+            messages: per_sender,
+            value: latency_args.amount,
+            reserve: 0,
+            deploys_itself: sender.kind == WalletKind::EverWallet,
         })
-        .context("No factory abi")?;
-    let deployment: GenericDeploymentInfo = serde_json::from_slice(&std::fs::read(wallet.path())?)?;
-    let sender = deployment.address;
-
-    log::info!("Sender address: {}", sender);
-
-    let initial_balance = client
-        .get_contract_state(&sender, None)
-        .await?
-        .unwrap()
-        .account
-        .storage
-        .balance
-        .grams
-        .as_u128();
-
-    let required_balance = COST_PER_TRANSACTION * latency_args.num_txs as u64;
-    let max_iterations = initial_balance / COST_PER_TRANSACTION as u128;
+        .collect();
+    preflight::check(&client, &common_args, &plan).await?;
+
+    let mut states = Vec::with_capacity(senders.len());
+    for sender in &senders {
+        let state = client
+            .get_contract_state(&sender.address, None)
+            .await?
+            .with_context(|| format!("No state for {}", sender.address))?;
+        states.push(Arc::new(state.account));
+    }
 
     log::info!(
         "Starting latency test - sending {} transactions at {} TPS",
         latency_args.num_txs,
         latency_args.rps
     );
-    log::info!(
-        "Initial balance: {}, required balance: {}, max iterations: {}",
-        initial_balance,
-        required_balance,
-        max_iterations
-    );
 
+    stats.set_target_rps(latency_args.rps as u64);
     let rl = RateLimiter::direct(governor::Quota::per_second(
         std::num::NonZeroU32::new(latency_args.rps).unwrap(),
     ));
 
     let mut csv_writer = if let Some(csv_path) = &latency_args.csv {
         let mut writer = std::fs::File::create(csv_path)?;
-        writeln!(writer, "latency_ns")?;
+        writeln!(writer, "{}", samples::CSV_HEADER)?;
         Some(writer)
     } else {
         None
     };
 
-    let mut latencies = Vec::with_capacity(latency_args.num_txs);
     let mut timestamped_latencies = Vec::with_capacity(latency_args.num_txs);
-    let mut success_count = 0;
     let mut error_count = 0;
 
-    let receiver = ton_block::MsgAddressInt::from_str(
+    let receiver = MsgAddressInt::from_str(
         "0:0000000000000000000000000000000000000000000000000000000000000000",
     )?;
-
-    for i in 0..std::cmp::min(latency_args.num_txs, max_iterations as usize) {
-        rl.until_ready().await;
-
-        let start = Instant::now();
-        let ts = SystemTime::now();
-
-        match send_test_transaction(&client, keypair, &sender, &receiver, latency_args.amount).await
-        {
-            Ok(_) => {
-                let latency = start.elapsed();
-                latencies.push(latency);
-                timestamped_latencies.push(plotting::TimestampedLatency {
-                    timestamp: ts,
-                    latency,
+    // The config contract lives in the masterchain, its state comes from the latest block there.
+    let config = client
+        .get_blockchain_config()
+        .await
+        .context("Failed to get blockchain config")?;
+    let masterchain_account =
+        MsgAddressInt::with_standart(None, -1, config.raw_config().config_addr.clone().into())?;
+
+    // Probes run concurrently, results come back in completion order.
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let producer = {
+        let (num_txs, amount) = (latency_args.num_txs, latency_args.amount);
+        let in_flight = Arc::new(Semaphore::new(latency_args.max_in_flight.max(1)));
+        let (client, stats) = (client.clone(), stats.clone());
+        tokio::spawn(async move {
+            for i in 0..num_txs {
+                rl.until_ready().await;
+                let permit = in_flight.clone().acquire_owned().await.unwrap();
+
+                let sender = senders[i % senders.len()].clone();
+                let state = states[i % states.len()].clone();
+                let (client, stats, receiver, tx) =
+                    (client.clone(), stats.clone(), receiver.clone(), tx.clone());
+                let masterchain_account = masterchain_account.clone();
+                tokio::spawn(async move {
+                    stats.record_built();
+                    let start = Instant::now();
+                    let sample = probe(
+                        &client,
+                        &masterchain_account,
+                        &sender,
+                        &state,
+                        &receiver,
+                        amount,
+                    )
+                    .await;
+                    // Inclusion is the headline latency, the one the report and SLA rules see.
+                    let latency = sample.phases.map_or(start.elapsed(), |p| p.inclusion);
+                    stats.record_result(latency, &sample.phases.ok_or(()));
+                    drop(permit);
+                    let _ = tx.send((i, sample));
                 });
-                success_count += 1;
-                log::debug!("Transaction {} succeeded in {:?}", i, latency);
+            }
+        })
+    };
+
+    while let Some((i, sample)) = rx.recv().await {
+        if let Some(writer) = &mut csv_writer {
+            sample.write_csv(writer)?;
+            writer.flush()?;
+        }
 
-                if let Some(writer) = &mut csv_writer {
-                    writeln!(writer, "{}", latency.as_nanos())?;
-                    writer.flush()?;
-                }
+        match sample.to_timestamped() {
+            Some(latency) => {
+                stats.add_confirmed(1);
+                log::debug!("Transaction {i} succeeded: {:?}", latency.phases);
+                timestamped_latencies.push(latency);
             }
-            Err(e) => {
+            None => {
                 error_count += 1;
-                log::error!("Transaction {} failed: {}", i, e);
+                log::error!(
+                    "Transaction {i} failed ({:?}): {}",
+                    sample.status,
+                    sample.error.as_deref().unwrap_or_default()
+                );
             }
         }
     }
-
-    // Calculate statistics
-    if !latencies.is_empty() {
-        latencies.sort();
-        let total: Duration = latencies.iter().sum();
-        let avg = total / latencies.len() as u32;
-        let p50 = latencies[latencies.len() / 2];
-        let p95 = latencies[(latencies.len() as f64 * 0.95) as usize];
-        let p99 = latencies[(latencies.len() as f64 * 0.99) as usize];
-        let min = latencies[0];
-        let max = latencies[latencies.len() - 1];
-
-        log::info!("Latency test results:");
-        log::info!("Successful transactions: {}", success_count);
-        log::info!("Failed transactions: {}", error_count);
-        log::info!("Average latency: {:?}", avg);
-        log::info!("P50 latency: {:?}", p50);
-        log::info!("P95 latency: {:?}", p95);
-        log::info!("P99 latency: {:?}", p99);
-
-        if let Some(plot_path) = latency_args.plot {
-            let stats = plotting::LatencyStats {
-                avg,
-                p50,
-                p95,
-                p99,
-                min,
-                max,
-            };
-
-            plotting::generate_combined_plots(
-                &latencies,
-                &timestamped_latencies,
-                plot_path.clone(),
-                &stats,
-                latency_args.time_window,
-                latency_args.sla_threshold.map(|t| t as f64),
-            )?;
-
-            log::info!("Plot saved to: {:?}", plot_path);
+    producer.await?;
+
+    log::info!("Latency test results:");
+    log::info!("Successful transactions: {}", timestamped_latencies.len());
+    log::info!("Failed transactions: {}", error_count);
+    if let Some(summary) = summarize(&timestamped_latencies) {
+        log::info!("Average latency: {:?}", summary.avg);
+        log::info!("P50 latency: {:?}", summary.p50);
+        log::info!("P95 latency: {:?}", summary.p95);
+        log::info!("P99 latency: {:?}", summary.p99);
+        for (name, phase) in plotting::PHASES {
+            let mut values: Vec<_> = timestamped_latencies
+                .iter()
+                .map(|x| phase(&x.phases))
+                .collect();
+            values.sort();
+            log::info!(
+                "{name}: P50 {:?}, P95 {:?}",
+                values[values.len() / 2],
+                values[(values.len() as f64 * 0.95) as usize]
+            );
         }
     }
 
+    if let Some(plot_path) = latency_args.plot.filter(|_| !timestamped_latencies.is_empty()) {
+        write_plot(
+            &timestamped_latencies,
+            plot_path,
+            latency_args.time_window,
+            latency_args.sla_threshold,
+            report_assets(latency_args.embed_assets),
+        )?;
+    }
+
     Ok(())
 }
 
-async fn send_test_transaction(
-    client: &RpcClient,
-    keypair: &Keypair,
-    sender: &ton_block::MsgAddressInt,
-    receiver: &ton_block::MsgAddressInt,
-    amount: u64,
+/// Rebuilds the HTML report of one or more `latency --csv` files without touching the network.
+pub(crate) fn plot(args: PlotArgs) -> Result<()> {
+    if args.compare {
+        return compare(args);
+    }
+
+    let mut timestamped_latencies = Vec::new();
+    for path in &args.csv {
+        timestamped_latencies.extend(load_csv(path)?);
+    }
+    timestamped_latencies.sort_by_key(|x| x.timestamp);
+
+    write_plot(
+        &timestamped_latencies,
+        args.output,
+        args.time_window,
+        args.sla_threshold,
+        report_assets(args.embed_assets),
+    )
+}
+
+fn compare(args: PlotArgs) -> Result<()> {
+    if !args.label.is_empty() && args.label.len() != args.csv.len() {
+        anyhow::bail!(
+            "Got {} labels for {} CSV files",
+            args.label.len(),
+            args.csv.len()
+        );
+    }
+
+    let mut datasets = Vec::with_capacity(args.csv.len());
+    for (i, path) in args.csv.iter().enumerate() {
+        let label = match args.label.get(i) {
+            Some(label) => label.clone(),
+            None => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string()),
+        };
+        datasets.push(plotting::LatencyDataset {
+            label,
+            data: load_csv(path)?,
+        });
+    }
+
+    plotting::generate_comparison_plots(
+        &datasets,
+        args.output.clone(),
+        args.time_window,
+        args.sla_threshold.map(|t| t as f64),
+        report_assets(args.embed_assets),
+    )?;
+    log::info!("Comparison saved to: {:?}", args.output);
+    Ok(())
+}
+
+/// Successful samples of a `latency --csv` file, ordered by send time.
+fn load_csv(path: &Path) -> Result<Vec<plotting::TimestampedLatency>> {
+    let samples = samples::read_csv(path)?;
+    let total = samples.len();
+    let mut latencies: Vec<_> = samples.iter().filter_map(|s| s.to_timestamped()).collect();
+    latencies.sort_by_key(|x| x.timestamp);
+    log::info!(
+        "Loaded {} successful and {} failed transactions from {:?}",
+        latencies.len(),
+        total - latencies.len(),
+        path
+    );
+    Ok(latencies)
+}
+
+fn summarize(data: &[plotting::TimestampedLatency]) -> Option<plotting::LatencyStats> {
+    let latencies: Vec<_> = data.iter().map(|x| x.latency).collect();
+    plotting::LatencyStats::compute(&latencies)
+}
+
+fn report_assets(embed: bool) -> plotting::ReportAssets {
+    if embed {
+        plotting::ReportAssets::Embedded
+    } else {
+        plotting::ReportAssets::Cdn
+    }
+}
+
+fn write_plot(
+    data: &[plotting::TimestampedLatency],
+    plot_path: PathBuf,
+    time_window: Option<u64>,
+    sla_threshold: Option<u64>,
+    assets: plotting::ReportAssets,
 ) -> Result<()> {
-    let payload = ton_types::BuilderData::new();
-    let state = client.get_contract_state(sender, None).await?.unwrap();
-    let balance = state.account.storage.balance.grams.as_u128();
-    log::info!("Sender balance: {}", balance);
-    let prev_lt = state.account.storage.last_trans_lt;
+    let Some(stats) = summarize(data) else {
+        anyhow::bail!("No successful transactions to plot");
+    };
+    let latencies: Vec<_> = data.iter().map(|x| x.latency).collect();
+
+    plotting::generate_combined_plots(
+        &latencies,
+        data,
+        plot_path.clone(),
+        &stats,
+        time_window,
+        sla_threshold.map(|t| t as f64),
+        assets,
+    )?;
 
-    send::send(
+    log::info!("Plot saved to: {:?}", plot_path);
+    Ok(())
+}
+
+/// Sends a transfer and follows it until the masterchain lt passes its transaction.
+///
+/// A masterchain block past the transaction lt is the earliest that could reference it, but that
+/// doesn't prove it does, hence the phase name. The endpoint serves no block lookups, the lt of
+/// the latest masterchain block is read from the state of `masterchain_account`.
+async fn probe(
+    client: &RpcClient,
+    masterchain_account: &MsgAddressInt,
+    sender: &Sender,
+    state: &AccountStuff,
+    receiver: &MsgAddressInt,
+    amount: u64,
+) -> samples::Sample {
+    let mut sample = samples::Sample::new(SystemTime::now());
+    let phases = probe_phases(
         client,
-        keypair,
-        sender.clone(),
-        payload,
-        receiver.clone(),
+        masterchain_account,
+        sender,
+        state,
+        receiver,
+        amount,
+        &mut sample,
+    );
+    match phases.await {
+        Ok(phases) => {
+            sample.status = samples::Status::Ok;
+            sample.phases = Some(phases);
+        }
+        Err(e) => sample.error = Some(format!("{e:#}")),
+    }
+    sample
+}
+
+/// Advances `sample.status` to the phase being waited for, so it names where a failure happened.
+async fn probe_phases(
+    client: &RpcClient,
+    masterchain_account: &MsgAddressInt,
+    sender: &Sender,
+    state: &AccountStuff,
+    receiver: &MsgAddressInt,
+    amount: u64,
+    sample: &mut samples::Sample,
+) -> Result<plotting::PhaseLatency> {
+    let start = Instant::now();
+    let transfer = Transfer {
+        destination: receiver.clone(),
         amount,
-        &state.account,
+        payload: sender.transfer_payload()?,
+    };
+    let sent = send::send_transfers(
+        client,
+        &sender.keypair,
+        sender.kind,
+        sender.address.clone(),
+        vec![transfer],
+        state,
     )
     .await?;
-
-    // Wait for the transaction to be included in the block
+    let broadcast = start.elapsed();
+    sample.message_hash = Some(sent.hash.to_hex_string());
+    sample.status = samples::Status::Expired;
+
+    let hash = &sent.hash;
+    let transaction = loop {
+        let lookup = endpoints::call(client, |client| async move {
+            client.get_dst_transaction(hash.as_slice()).await
+        });
+        match lookup.await {
+            Ok(Some(transaction)) => break transaction,
+            Ok(None) => {}
+            Err(e) => log::debug!("Failed to look up transaction: {e:?}"),
+        }
+        if nekoton_utils::now_sec_u64() > sent.expire_at as u64 {
+            anyhow::bail!("Message {} expired", sent.hash.to_hex_string());
+        }
+        tokio::time::sleep(CONFIRMATION_POLL_INTERVAL).await;
+    };
+    sample.lt = Some(transaction.logical_time());
+    sample.status = samples::Status::McLtPending;
+    // Transaction time has a one second resolution.
+    let inclusion = (UNIX_EPOCH + Duration::from_secs(transaction.now() as u64))
+        .duration_since(sample.timestamp)
+        .unwrap_or_default();
+
+    let deadline = Instant::now() + MC_LT_TIMEOUT;
     loop {
-        let state = client.get_contract_state(sender, None).await?.unwrap();
-        if state.account.storage.last_trans_lt != prev_lt {
-            break;
+        match masterchain_lt(client, masterchain_account).await {
+            Ok(lt) if lt > transaction.logical_time() => break,
+            Ok(_) => {}
+            Err(e) => log::debug!("Failed to get masterchain state: {e:?}"),
+        }
+        if Instant::now() > deadline {
+            anyhow::bail!(
+                "Masterchain lt has not passed the transaction of message {} after {:?}",
+                sent.hash.to_hex_string(),
+                MC_LT_TIMEOUT
+            );
         }
-        tokio::time::sleep(Duration::from_millis(10)).await;
+        tokio::time::sleep(CONFIRMATION_POLL_INTERVAL).await;
     }
 
-    Ok(())
+    Ok(plotting::PhaseLatency {
+        broadcast,
+        inclusion,
+        masterchain_lt_passed: start.elapsed(),
+    })
+}
+
+/// Logical time of the masterchain block the endpoint served the account state from.
+async fn masterchain_lt(client: &RpcClient, account: &MsgAddressInt) -> Result<u64> {
+    let state = endpoints::call(client, |client| async move {
+        client.get_contract_state(account, None).await
+    })
+    .await?
+    .with_context(|| format!("No state for {account}"))?;
+    match state.timings {
+        GenTimings::Known { gen_lt, .. } => Ok(gen_lt),
+        GenTimings::Unknown => anyhow::bail!("No generation time for {account}"),
+    }
 }
@@ -0,0 +1,19 @@
+#!/bin/sh
+# Downloads the scripts and styles inlined into `--embed-assets` latency reports, along with their
+# licenses. Every build inlines them, commit the files. Versions match the CDN links of the
+# reports.
+set -eu
+cd "$(dirname "$0")"
+
+curl -fsSL -o echarts.min.js \
+    https://cdn.jsdelivr.net/npm/echarts@5.5.1/dist/echarts.min.js
+curl -fsSL -o LICENSE-echarts \
+    https://cdn.jsdelivr.net/npm/echarts@5.5.1/LICENSE
+curl -fsSL -o NOTICE-echarts \
+    https://cdn.jsdelivr.net/npm/echarts@5.5.1/NOTICE
+curl -fsSL -o bootstrap.min.css \
+    https://cdn.jsdelivr.net/npm/bootstrap@5.3.0/dist/css/bootstrap.min.css
+curl -fsSL -o bootstrap.bundle.min.js \
+    https://cdn.jsdelivr.net/npm/bootstrap@5.3.0/dist/js/bootstrap.bundle.min.js
+curl -fsSL -o LICENSE-bootstrap \
+    https://cdn.jsdelivr.net/npm/bootstrap@5.3.0/LICENSE
@@ -3,32 +3,32 @@ use std::fs;
 use std::path::Path;
 use crate::latency::plotting::LatencyStats;
 
-pub fn generate_combined_html(
-    density_chart_option: &str,
-    timeseries_chart_option: &str,
-    interactive_chart_option: &str,
-    stats: &LatencyStats,
-    output_path: &Path,
-) -> Result<()> {
-    let html_content = format!(
-        r##"<!DOCTYPE html>
-<html>
-  <head>
-    <meta charset="utf-8" />
-    <title>Latency Analysis</title>
-    <script src="https://cdn.jsdelivr.net/npm/echarts@5.5.1/dist/echarts.min.js"></script>
+/// Where a report loads its scripts and styles from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReportAssets {
+    /// jsDelivr and Google Fonts, the machine opening the report needs internet access.
+    #[default]
+    Cdn,
+    /// echarts and Bootstrap inlined from `src/latency/assets`, the page makes no requests and
+    /// text falls back to the system font.
+    Embedded,
+}
+
+const CDN_ASSETS: &str = r##"    <script src="https://cdn.jsdelivr.net/npm/echarts@5.5.1/dist/echarts.min.js"></script>
     <script src="https://cdn.jsdelivr.net/npm/echarts-gl@2.0.9/dist/echarts-gl.min.js"></script>
     <link href="https://cdn.jsdelivr.net/npm/bootstrap@5.3.0/dist/css/bootstrap.min.css" rel="stylesheet">
     <script src="https://cdn.jsdelivr.net/npm/bootstrap@5.3.0/dist/js/bootstrap.bundle.min.js"></script>
     <link rel="preconnect" href="https://fonts.googleapis.com">
     <link rel="preconnect" href="https://fonts.gstatic.com" crossorigin>
-    <link href="https://fonts.googleapis.com/css2?family=Inter:wght@300;400;500;600&display=swap" rel="stylesheet">
-    <style>
-      * {{
+    <link href="https://fonts.googleapis.com/css2?family=Inter:wght@300;400;500;600&display=swap" rel="stylesheet">"##;
+
+/// Styles shared by the single run and the comparison reports.
+const PAGE_STYLE: &str = r##"    <style>
+      * {
         box-sizing: border-box;
-      }}
+      }
       
-      body {{
+      body {
         background: #0a0e27;
         color: #e4e6eb;
         font-family: 'Inter', -apple-system, BlinkMacSystemFont, 'Segoe UI', sans-serif;
@@ -39,9 +39,9 @@ pub fn generate_combined_html(
         overflow: hidden;
         display: flex;
         flex-direction: column;
-      }}
+      }
       
-      body::before {{
+      body::before {
         content: '';
         position: fixed;
         top: 0;
@@ -52,9 +52,9 @@ pub fn generate_combined_html(
                     radial-gradient(circle at 80% 80%, rgba(255, 0, 110, 0.1) 0%, transparent 50%);
         pointer-events: none;
         z-index: 1;
-      }}
+      }
       
-      .container {{ 
+      .container { 
         margin: 0 auto;
         width: 100%;
         max-width: 100%;
@@ -65,9 +65,9 @@ pub fn generate_combined_html(
         display: flex;
         flex-direction: column;
         overflow: hidden;
-      }}
+      }
       
-      .stats-panel {{
+      .stats-panel {
         background: #1a1f3a;
         border-radius: 8px;
         padding: 12px;
@@ -75,57 +75,57 @@ pub fn generate_combined_html(
         box-shadow: 0 3px 10px rgba(0, 0, 0, 0.5);
         border: 1px solid #2a3451;
         animation: fadeInDown 0.4s ease-out;
-      }}
+      }
       
-      .stats-grid {{
+      .stats-grid {
         display: grid;
         grid-template-columns: repeat(auto-fit, minmax(150px, 1fr));
         gap: 20px;
-      }}
+      }
       
-      .stat-item {{
+      .stat-item {
         text-align: center;
         padding: 8px;
         background: rgba(37, 43, 72, 0.5);
         border-radius: 8px;
         border: 1px solid rgba(42, 52, 81, 0.5);
         transition: all 0.2s ease;
-      }}
+      }
       
-      .stat-item:hover {{
+      .stat-item:hover {
         transform: translateY(-2px);
         box-shadow: 0 4px 12px rgba(0, 0, 0, 0.3);
         border-color: #00d4ff;
-      }}
+      }
       
-      .stat-label {{
+      .stat-label {
         font-size: 12px;
         color: #64748b;
         text-transform: uppercase;
         letter-spacing: 0.05em;
         margin-bottom: 8px;
-      }}
+      }
       
-      .stat-value {{
+      .stat-value {
         font-size: 20px;
         font-weight: 600;
         color: #00d4ff;
-      }}
+      }
       
-      .charts-grid {{
+      .charts-grid {
         display: grid;
         grid-template-columns: 1fr 1fr;
         gap: 12px;
         height: 100%;
         flex: 1;
-      }}
+      }
       
-      .full-width {{
+      .full-width {
         grid-column: 1 / -1;
         height: 100%;
-      }}
+      }
       
-      h1 {{
+      h1 {
         font-weight: 300;
         letter-spacing: -0.02em;
         font-size: 1.5rem;
@@ -135,36 +135,36 @@ pub fn generate_combined_html(
         background-clip: text;
         margin: 0.5rem 0;
         animation: fadeInDown 0.4s ease-out;
-      }}
+      }
       
-      @keyframes fadeInDown {{
-        from {{
+      @keyframes fadeInDown {
+        from {
           opacity: 0;
           transform: translateY(-20px);
-        }}
-        to {{
+        }
+        to {
           opacity: 1;
           transform: translateY(0);
-        }}
-      }}
+        }
+      }
       
-      .nav-tabs {{
+      .nav-tabs {
         border: none;
         gap: 8px;
         margin-bottom: 12px;
         animation: fadeIn 0.5s ease-out 0.15s both;
-      }}
+      }
       
-      @keyframes fadeIn {{
-        from {{
+      @keyframes fadeIn {
+        from {
           opacity: 0;
-        }}
-        to {{
+        }
+        to {
           opacity: 1;
-        }}
-      }}
+        }
+      }
       
-      .nav-link {{
+      .nav-link {
         background: #1a1f3a;
         border: 1px solid #2a3451;
         color: #64748b;
@@ -174,9 +174,9 @@ pub fn generate_combined_html(
         font-weight: 500;
         position: relative;
         overflow: hidden;
-      }}
+      }
       
-      .nav-link::before {{
+      .nav-link::before {
         content: '';
         position: absolute;
         top: 0;
@@ -185,28 +185,28 @@ pub fn generate_combined_html(
         height: 100%;
         background: linear-gradient(90deg, transparent, rgba(0, 212, 255, 0.1), transparent);
         transition: left 0.3s ease;
-      }}
+      }
       
-      .nav-link:hover {{
+      .nav-link:hover {
         background: #252b48;
         color: #e4e6eb;
         transform: translateY(-2px);
         box-shadow: 0 4px 12px rgba(0, 0, 0, 0.3);
-      }}
+      }
       
-      .nav-link:hover::before {{
+      .nav-link:hover::before {
         left: 100%;
-      }}
+      }
       
-      .nav-link.active {{
+      .nav-link.active {
         background: #252b48;
         border-color: #00d4ff;
         border-bottom: 2px solid #00d4ff;
         color: #00d4ff;
         box-shadow: 0 4px 20px rgba(0, 212, 255, 0.2);
-      }}
+      }
       
-      .chart-container {{
+      .chart-container {
         background: #1a1f3a;
         border-radius: 8px;
         padding: 12px;
@@ -216,51 +216,51 @@ pub fn generate_combined_html(
         height: 100%;
         position: relative;
         animation: fadeInUp 0.4s ease-out;
-      }}
+      }
       
-      .chart-container-small {{
+      .chart-container-small {
         height: 100%;
-      }}
+      }
       
-      @keyframes fadeInUp {{
-        from {{
+      @keyframes fadeInUp {
+        from {
           opacity: 0;
           transform: translateY(20px);
-        }}
-        to {{
+        }
+        to {
           opacity: 1;
           transform: translateY(0);
-        }}
-      }}
+        }
+      }
       
-      .tab-content {{
+      .tab-content {
         padding-top: 0;
         flex: 1;
         display: flex;
         overflow: hidden;
-      }}
+      }
       
-      .tab-pane {{
+      .tab-pane {
         animation: fadeIn 0.3s ease-out;
         width: 100%;
         height: 100%;
         display: flex;
         flex-direction: column;
-      }}
+      }
       
       /* Override Bootstrap dark theme conflicts */
       .nav-tabs .nav-link:focus,
-      .nav-tabs .nav-link:hover {{
+      .nav-tabs .nav-link:hover {
         border-color: #2a3451;
-      }}
+      }
       
       .nav-tabs .nav-link.active:focus,
-      .nav-tabs .nav-link.active:hover {{
+      .nav-tabs .nav-link.active:hover {
         border-color: #00d4ff #00d4ff #00d4ff;
-      }}
+      }
       
       /* Loading spinner */
-      .loading {{
+      .loading {
         display: inline-block;
         width: 20px;
         height: 20px;
@@ -273,14 +273,14 @@ pub fn generate_combined_html(
         top: 50%;
         left: 50%;
         transform: translate(-50%, -50%);
-      }}
+      }
       
-      @keyframes spin {{
-        to {{ transform: translate(-50%, -50%) rotate(360deg); }}
-      }}
+      @keyframes spin {
+        to { transform: translate(-50%, -50%) rotate(360deg); }
+      }
       
       /* Subtle background pattern */
-      .chart-container::after {{
+      .chart-container::after {
         content: '';
         position: absolute;
         top: 0;
@@ -291,8 +291,98 @@ pub fn generate_combined_html(
         background-size: 40px 40px;
         pointer-events: none;
         border-radius: 16px;
-      }}
-    </style>
+      }
+      
+      .tables-grid {
+        display: grid;
+        grid-template-columns: 1fr 1fr;
+        gap: 20px;
+      }
+      
+      .stats-table {
+        width: 100%;
+        border-collapse: collapse;
+        font-size: 14px;
+      }
+      
+      .stats-table th {
+        font-size: 12px;
+        font-weight: 500;
+        color: #64748b;
+        text-transform: uppercase;
+        letter-spacing: 0.05em;
+        text-align: right;
+        padding: 6px 10px;
+        border-bottom: 1px solid #2a3451;
+      }
+      
+      .stats-table td {
+        text-align: right;
+        padding: 6px 10px;
+        border-bottom: 1px solid rgba(42, 52, 81, 0.5);
+      }
+      
+      .stats-table th:first-child,
+      .stats-table td:first-child {
+        text-align: left;
+        color: #e4e6eb;
+      }
+      
+      .delta-worse {
+        color: #ff6b6b;
+      }
+      
+      .delta-better {
+        color: #40e0d0;
+      }
+    </style>"##;
+
+fn page_head(assets: ReportAssets) -> String {
+    let assets = match assets {
+        ReportAssets::Cdn => CDN_ASSETS.to_string(),
+        ReportAssets::Embedded => embedded_assets(),
+    };
+    format!("{assets}\n{PAGE_STYLE}")
+}
+
+/// Vendored copies of the CDN assets, see `assets/README.md`.
+fn embedded_assets() -> String {
+    const ECHARTS: &str = include_str!("assets/echarts.min.js");
+    const BOOTSTRAP_CSS: &str = include_str!("assets/bootstrap.min.css");
+    const BOOTSTRAP_JS: &str = include_str!("assets/bootstrap.bundle.min.js");
+
+    // Keeps an inlined script from closing its element early
+    let script = |js: &str| js.replace("</script", "<\\/script");
+    format!(
+        "    <style>\n{BOOTSTRAP_CSS}\n    </style>\n    <script>\n{}\n    </script>\n    <script>\n{}\n    </script>",
+        script(ECHARTS),
+        script(BOOTSTRAP_JS),
+    )
+}
+
+/// Summary of one run in the comparison report.
+pub struct ComparedRun<'a> {
+    pub label: &'a str,
+    pub samples: usize,
+    pub stats: LatencyStats,
+}
+
+pub fn generate_combined_html(
+    density_chart_option: &str,
+    timeseries_chart_option: &str,
+    interactive_chart_option: &str,
+    phases_chart_option: &str,
+    stats: &LatencyStats,
+    output_path: &Path,
+    assets: ReportAssets,
+) -> Result<()> {
+    let html_content = format!(
+        r##"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8" />
+    <title>Latency Analysis</title>
+{page_head}
   </head>
   <body>
     <div class="container">
@@ -338,6 +428,11 @@ pub fn generate_combined_html(
             Detailed Analysis
           </button>
         </li>
+        <li class="nav-item" role="presentation">
+          <button class="nav-link" id="phases-tab" data-bs-toggle="tab" data-bs-target="#phases" type="button" role="tab" aria-controls="phases" aria-selected="false">
+            Phases
+          </button>
+        </li>
       </ul>
       
       <div class="tab-content" id="plotTabContent">
@@ -350,6 +445,9 @@ pub fn generate_combined_html(
         <div class="tab-pane fade" id="detailed" role="tabpanel" aria-labelledby="detailed-tab">
           <div id="interactiveChart" class="chart-container full-width"></div>
         </div>
+        <div class="tab-pane fade" id="phases" role="tabpanel" aria-labelledby="phases-tab">
+          <div id="phasesChart" class="chart-container full-width"></div>
+        </div>
       </div>
     </div>
     
@@ -357,10 +455,12 @@ pub fn generate_combined_html(
       var densityChart = echarts.init(document.getElementById('densityChart'), 'dark');
       var timeseriesChart = echarts.init(document.getElementById('timeseriesChart'), 'dark');
       var interactiveChart = echarts.init(document.getElementById('interactiveChart'), 'dark');
+      var phasesChart = echarts.init(document.getElementById('phasesChart'), 'dark');
       
       var densityOption = {density_option};
       var timeseriesOption = {timeseries_option};
       var interactiveOption = {interactive_option};
+      var phasesOption = {phases_option};
       
       densityChart.setOption(densityOption);
       timeseriesChart.setOption(timeseriesOption);
@@ -370,10 +470,16 @@ pub fn generate_combined_html(
         interactiveChart.resize();
       }});
       
+      document.getElementById('phases-tab').addEventListener('shown.bs.tab', function (e) {{
+        phasesChart.setOption(phasesOption);
+        phasesChart.resize();
+      }});
+      
       window.addEventListener('resize', function() {{
         densityChart.resize();
         timeseriesChart.resize();
         interactiveChart.resize();
+        phasesChart.resize();
       }});
       
       // Initial resize to fill containers
@@ -381,6 +487,7 @@ pub fn generate_combined_html(
         densityChart.resize();
         timeseriesChart.resize();
         interactiveChart.resize();
+        phasesChart.resize();
       }}, 100);
     </script>
   </body>
@@ -388,6 +495,8 @@ pub fn generate_combined_html(
         density_option = density_chart_option,
         timeseries_option = timeseries_chart_option,
         interactive_option = interactive_chart_option,
+        phases_option = phases_chart_option,
+        page_head = page_head(assets),
         avg_ms = stats.avg.as_millis(),
         p50_ms = stats.p50.as_millis(),
         p95_ms = stats.p95.as_millis(),
@@ -398,4 +507,137 @@ pub fn generate_combined_html(
 
     fs::write(output_path, html_content)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+pub fn generate_comparison_html(
+    density_chart_option: &str,
+    timeseries_chart_option: &str,
+    runs: &[ComparedRun],
+    output_path: &Path,
+    assets: ReportAssets,
+) -> Result<()> {
+    let ms = |d: std::time::Duration| d.as_millis() as i64;
+
+    let percentile_rows: String = runs
+        .iter()
+        .map(|run| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(run.label),
+                run.samples,
+                ms(run.stats.avg),
+                ms(run.stats.p50),
+                ms(run.stats.p95),
+                ms(run.stats.p99),
+                ms(run.stats.min),
+                ms(run.stats.max),
+            )
+        })
+        .collect();
+
+    let baseline = &runs[0];
+    let delta_rows: String = runs[1..]
+        .iter()
+        .map(|run| {
+            let delta = |value: fn(&LatencyStats) -> std::time::Duration| {
+                let (before, after) = (ms(value(&baseline.stats)), ms(value(&run.stats)));
+                let diff = after - before;
+                let class = match diff {
+                    0 => "",
+                    d if d > 0 => "delta-worse",
+                    _ => "delta-better",
+                };
+                let percent = if before > 0 {
+                    format!(" ({:+.1}%)", diff as f64 * 100.0 / before as f64)
+                } else {
+                    String::new()
+                };
+                format!("<td class=\"{class}\">{diff:+} ms{percent}</td>")
+            };
+            format!(
+                "<tr><td>{}</td>{}{}{}</tr>",
+                escape_html(run.label),
+                delta(|s| s.p50),
+                delta(|s| s.p95),
+                delta(|s| s.p99),
+            )
+        })
+        .collect();
+
+    let html_content = format!(
+        r##"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8" />
+    <title>Latency Comparison</title>
+{page_head}
+  </head>
+  <body>
+    <div class="container">
+      <h1 class="text-center mb-4">Latency Comparison</h1>
+      
+      <div class="stats-panel">
+        <div class="tables-grid">
+          <table class="stats-table">
+            <thead>
+              <tr><th>Run</th><th>N</th><th>Avg, ms</th><th>P50, ms</th><th>P95, ms</th><th>P99, ms</th><th>Min, ms</th><th>Max, ms</th></tr>
+            </thead>
+            <tbody>
+              {percentile_rows}
+            </tbody>
+          </table>
+          <table class="stats-table">
+            <thead>
+              <tr><th>Change vs {baseline_label}</th><th>P50</th><th>P95</th><th>P99</th></tr>
+            </thead>
+            <tbody>
+              {delta_rows}
+            </tbody>
+          </table>
+        </div>
+      </div>
+      
+      <div class="charts-grid">
+        <div id="densityChart" class="chart-container"></div>
+        <div id="timeseriesChart" class="chart-container"></div>
+      </div>
+    </div>
+    
+    <script type="text/javascript">
+      var densityChart = echarts.init(document.getElementById('densityChart'), 'dark');
+      var timeseriesChart = echarts.init(document.getElementById('timeseriesChart'), 'dark');
+      
+      densityChart.setOption({density_option});
+      timeseriesChart.setOption({timeseries_option});
+      
+      window.addEventListener('resize', function() {{
+        densityChart.resize();
+        timeseriesChart.resize();
+      }});
+      
+      // Initial resize to fill containers
+      setTimeout(() => {{
+        densityChart.resize();
+        timeseriesChart.resize();
+      }}, 100);
+    </script>
+  </body>
+</html>"##,
+        page_head = page_head(assets),
+        baseline_label = escape_html(baseline.label),
+        percentile_rows = percentile_rows,
+        delta_rows = delta_rows,
+        density_option = density_chart_option,
+        timeseries_option = timeseries_chart_option,
+    );
+
+    fs::write(output_path, html_content)?;
+    Ok(())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
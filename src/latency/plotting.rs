@@ -1,4 +1,4 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use charming::{
     component::{
         Axis, DataZoom, DataZoomType, Feature, Grid, Legend, 
@@ -21,6 +21,8 @@ use std::path::PathBuf;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use chrono::{DateTime, Utc};
 
+pub use crate::latency::combined_plot::ReportAssets;
+
 #[derive(Debug, Clone)]
 pub struct LatencyStats {
     pub avg: Duration,
@@ -31,6 +33,42 @@ pub struct LatencyStats {
     pub max: Duration,
 }
 
+impl LatencyStats {
+    pub fn compute(latencies: &[Duration]) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        let mut sorted = latencies.to_vec();
+        sorted.sort();
+        let total: Duration = sorted.iter().sum();
+        Some(Self {
+            avg: total / sorted.len() as u32,
+            p50: sorted[sorted.len() / 2],
+            p95: sorted[(sorted.len() as f64 * 0.95) as usize],
+            p99: sorted[(sorted.len() as f64 * 0.99) as usize],
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+        })
+    }
+}
+
+/// One labelled run of a comparison report.
+#[derive(Debug, Clone)]
+pub struct LatencyDataset {
+    pub label: String,
+    pub data: Vec<TimestampedLatency>,
+}
+
+/// Line and band colors of compared runs, reused when there are more runs.
+const COMPARISON_COLORS: [(&str, &str); 6] = [
+    ("#00d4ff", "rgba(0, 212, 255, 0.2)"),
+    ("#ffa500", "rgba(255, 165, 0, 0.2)"),
+    ("#ff006e", "rgba(255, 0, 110, 0.2)"),
+    ("#40e0d0", "rgba(64, 224, 208, 0.2)"),
+    ("#a78bfa", "rgba(167, 139, 250, 0.2)"),
+    ("#facc15", "rgba(250, 204, 21, 0.2)"),
+];
+
 fn build_density_chart(
     latencies: &[Duration],
     stats: &LatencyStats,
@@ -249,8 +287,27 @@ fn percentile(sorted_data: &[f64], p: f64) -> f64 {
 pub struct TimestampedLatency {
     pub timestamp: SystemTime,
     pub latency: Duration,
+    pub phases: PhaseLatency,
 }
 
+/// Parts of a latency sample, each measured from the moment the message was sent.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PhaseLatency {
+    /// Until the endpoint acknowledged the broadcast.
+    pub broadcast: Duration,
+    /// Until the transaction time, one second resolution.
+    pub inclusion: Duration,
+    /// Until the endpoint serves masterchain state with a logical time past the transaction. Not
+    /// a proof that a masterchain block references the shard block.
+    pub masterchain_lt_passed: Duration,
+}
+
+pub const PHASES: [(&str, fn(&PhaseLatency) -> Duration); 3] = [
+    ("Broadcast", |p| p.broadcast),
+    ("Inclusion", |p| p.inclusion),
+    ("Masterchain LT passed", |p| p.masterchain_lt_passed),
+];
+
 fn calculate_optimal_window_seconds(data: &[TimestampedLatency]) -> u64 {
     if data.is_empty() { 
         return 60; // default 1 minute
@@ -620,6 +677,343 @@ fn build_interactive_time_series_chart(
     chart
 }
 
+fn build_phase_breakdown_chart(data: &[TimestampedLatency]) -> Chart {
+    let mut sorted_data = data.to_vec();
+    sorted_data.sort_by_key(|item| item.timestamp);
+
+    let timestamps: Vec<String> = sorted_data
+        .iter()
+        .map(|item| {
+            DateTime::<Utc>::from(item.timestamp)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .collect();
+
+    let mut chart = Chart::new()
+        .title(
+            Title::new()
+                .text("Latency Phases")
+                .subtext("Broadcast acknowledgment, transaction time and masterchain commit")
+                .left("center")
+                .text_style(TextStyle::new().color("#e4e6eb").font_size(18))
+                .subtext_style(TextStyle::new().color("#64748b")),
+        )
+        .tooltip(
+            Tooltip::new()
+                .trigger(Trigger::Axis)
+                .axis_pointer(AxisPointer::new().type_(AxisPointerType::Cross)),
+        )
+        .legend(
+            Legend::new()
+                .top("8%")
+                .text_style(TextStyle::new().color("#e4e6eb")),
+        )
+        .grid(
+            Grid::new()
+                .left("5%")
+                .right("3%")
+                .bottom("15%")
+                .top("15%")
+                .contain_label(true),
+        )
+        .x_axis(
+            Axis::new()
+                .type_(AxisType::Category)
+                .data(timestamps)
+                .boundary_gap(false)
+                .axis_label(AxisLabel::new()
+                    .color("#64748b")
+                    .formatter(JsFunction::new_with_args("value",
+                        "return value.split(' ')[1] || value;"
+                    ))),
+        )
+        .y_axis(
+            Axis::new()
+                .type_(AxisType::Value)
+                .name("Latency (ms)")
+                .name_location(NameLocation::Middle)
+                .name_gap(40)
+                .name_text_style(TextStyle::new().color("#64748b"))
+                .axis_label(AxisLabel::new().color("#64748b"))
+                .split_line(SplitLine::new().line_style(LineStyle::new().color("#2a3451"))),
+        )
+        .data_zoom(DataZoom::new().type_(DataZoomType::Inside))
+        .data_zoom(DataZoom::new().show(true));
+
+    let colors = ["#00d4ff", "#ffa500", "#ff006e"];
+    for ((name, phase), color) in PHASES.iter().zip(colors) {
+        let values: Vec<f64> = sorted_data
+            .iter()
+            .map(|item| phase(&item.phases).as_millis() as f64)
+            .collect();
+        chart = chart.series(
+            Line::new()
+                .name(*name)
+                .data(values)
+                .symbol(Symbol::None)
+                .line_style(LineStyle::new().width(2).color(color)),
+        );
+    }
+
+    chart
+}
+
+fn build_density_comparison_chart(datasets: &[LatencyDataset]) -> Chart {
+    let labels: Vec<&str> = datasets.iter().map(|d| d.label.as_str()).collect();
+
+    let mut chart = Chart::new()
+        .title(
+            Title::new()
+                .text("Latency Distribution")
+                .left("center")
+                .top("5%")
+                .text_style(TextStyle::new().font_size(18).color("#e4e6eb")),
+        )
+        .legend(
+            Legend::new()
+                .data(labels)
+                .top("5%")
+                .right("5%")
+                .orient(Orient::Vertical)
+                .text_style(TextStyle::new().color("#64748b"))
+                .item_width(25)
+                .item_height(14),
+        )
+        .tooltip(
+            Tooltip::new()
+                .trigger(Trigger::Axis)
+                .axis_pointer(AxisPointer::new().type_(AxisPointerType::Line)),
+        )
+        .toolbox(
+            Toolbox::new()
+                .feature(Feature::new()
+                    .restore(Restore::new())
+                    .save_as_image(SaveAsImage::new())),
+        )
+        .grid(
+            Grid::new()
+                .left("8%")
+                .right("12%")
+                .top("8%")
+                .bottom("15%"),
+        )
+        .x_axis(
+            Axis::new()
+                .type_(AxisType::Value)
+                .name("Latency (ms)")
+                .name_location(NameLocation::Middle)
+                .name_gap(30)
+                .name_text_style(TextStyle::new().color("#64748b"))
+                .split_line(SplitLine::new().show(false))
+                .axis_label(AxisLabel::new()
+                    .color("#64748b")
+                    .formatter("{value} ms")),
+        )
+        .y_axis(
+            Axis::new()
+                .type_(AxisType::Value)
+                .name("Density")
+                .name_location(NameLocation::Middle)
+                .name_gap(40)
+                .name_text_style(TextStyle::new().color("#64748b"))
+                .split_line(SplitLine::new().show(true).line_style(LineStyle::new().color("#2a3451")))
+                .axis_label(AxisLabel::new().color("#64748b"))
+                .axis_line(AxisLine::new().show(false))
+                .axis_tick(AxisTick::new().show(false)),
+        );
+
+    for (dataset, (line, area)) in datasets.iter().zip(COMPARISON_COLORS.iter().cycle()) {
+        let latencies: Vec<f64> = dataset.data.iter().map(|d| d.latency.as_millis() as f64).collect();
+        let bandwidth = silverman_bandwidth(&latencies) * 0.7;
+        let (kde_x, kde_y) = kernel_density_estimation(&latencies, bandwidth, 500);
+        chart = chart.series(
+            Line::new()
+                .name(&dataset.label)
+                .data(
+                    kde_x.into_iter()
+                        .zip(kde_y)
+                        .map(|(x, y)| vec![x, y])
+                        .collect::<Vec<_>>()
+                )
+                .symbol(Symbol::None)
+                .smooth(0.3)
+                .line_style(LineStyle::new().width(2).color(*line))
+                .area_style(AreaStyle::new().color(*area)),
+        );
+    }
+
+    chart
+}
+
+/// Buckets by time since the first sample, so runs made at different times line up.
+fn bucket_by_offset(
+    data: &[TimestampedLatency],
+    window_seconds: u64,
+) -> HashMap<i64, Vec<f64>> {
+    let mut buckets: HashMap<i64, Vec<f64>> = HashMap::new();
+    let Some(start) = data.iter().map(|d| d.timestamp).min() else {
+        return buckets;
+    };
+
+    for item in data {
+        let offset = item.timestamp.duration_since(start).unwrap_or_default().as_secs();
+        let bucket = (offset / window_seconds) * window_seconds;
+        buckets.entry(bucket as i64)
+            .or_default()
+            .push(item.latency.as_millis() as f64);
+    }
+
+    buckets
+}
+
+fn build_time_series_comparison_chart(
+    datasets: &[LatencyDataset],
+    window_minutes: Option<u64>,
+    sla_threshold: Option<f64>,
+) -> Chart {
+    // One window for all runs keeps their buckets comparable
+    let window_seconds = match window_minutes {
+        Some(minutes) => minutes * 60,
+        None => datasets
+            .iter()
+            .map(|d| calculate_optimal_window_seconds(&d.data))
+            .max()
+            .unwrap_or(60),
+    };
+    let labels: Vec<&str> = datasets.iter().map(|d| d.label.as_str()).collect();
+
+    let mut chart = Chart::new()
+        .title(
+            Title::new()
+                .text("Latency Over Time with Confidence Bands")
+                .subtext(format!("P50 lines and P10-P90 bands per {window_seconds} s window"))
+                .left("center")
+                .top("2%")
+                .text_style(TextStyle::new().color("#e4e6eb").font_size(18))
+                .subtext_style(TextStyle::new().color("#64748b")),
+        )
+        .tooltip(
+            Tooltip::new()
+                .trigger(Trigger::Item)
+                .formatter(JsFunction::new_with_args("params",
+                    "return params.seriesName + '<br/>+' + params.value[0] + ' s: P50 ' +
+                            params.value[1].toFixed(0) + ' ms';"
+                )),
+        )
+        .legend(
+            Legend::new()
+                .data(labels)
+                .top("12%")
+                .text_style(TextStyle::new().color("#64748b"))
+                .item_width(30)
+                .item_height(14),
+        )
+        .toolbox(
+            Toolbox::new()
+                .feature(Feature::new()
+                    .data_zoom(ToolboxDataZoom::new().y_axis_index("none"))
+                    .restore(Restore::new())
+                    .save_as_image(SaveAsImage::new())),
+        )
+        .grid(
+            Grid::new()
+                .left("5%")
+                .right("3%")
+                .bottom("12%")
+                .top("18%")
+                .contain_label(true),
+        )
+        .x_axis(
+            Axis::new()
+                .type_(AxisType::Value)
+                .name("Time since start (s)")
+                .name_location(NameLocation::Middle)
+                .name_gap(30)
+                .name_text_style(TextStyle::new().color("#64748b"))
+                .split_line(SplitLine::new().show(false))
+                .axis_label(AxisLabel::new().color("#64748b")),
+        )
+        .y_axis(
+            Axis::new()
+                .type_(AxisType::Value)
+                .name("Latency (ms)")
+                .name_location(NameLocation::Middle)
+                .name_gap(40)
+                .name_text_style(TextStyle::new().color("#64748b"))
+                .axis_label(AxisLabel::new().color("#64748b").formatter("{value}"))
+                .split_line(SplitLine::new().line_style(LineStyle::new().color("#2a3451")))
+                .min(0),
+        )
+        .data_zoom(DataZoom::new().type_(DataZoomType::Inside).start(0).end(100))
+        .data_zoom(DataZoom::new().start(0).end(100));
+
+    for (dataset, (line, area)) in datasets.iter().zip(COMPARISON_COLORS.iter().cycle()) {
+        let buckets = bucket_by_offset(&dataset.data, window_seconds);
+        let percentile_data = calculate_percentiles_by_bucket(&buckets, &[10.0, 50.0, 90.0]);
+
+        let mut offsets: Vec<_> = percentile_data.keys().copied().collect();
+        offsets.sort();
+        let points = |value: fn(&[f64]) -> f64| -> Vec<Vec<f64>> {
+            offsets
+                .iter()
+                .map(|offset| vec![*offset as f64, value(&percentile_data[offset])])
+                .collect()
+        };
+
+        // Series share the run label, so its legend entry toggles the band together with the line
+        chart = chart
+            .series(
+                Line::new()
+                    .name(&dataset.label)
+                    .data(points(|p| p[0]))
+                    .line_style(LineStyle::new().opacity(0))
+                    .stack(dataset.label.as_str())
+                    .symbol(Symbol::None),
+            )
+            .series(
+                Line::new()
+                    .name(&dataset.label)
+                    .data(points(|p| p[2] - p[0]))
+                    .line_style(LineStyle::new().opacity(0))
+                    .area_style(AreaStyle::new().color(*area))
+                    .stack(dataset.label.as_str())
+                    .symbol(Symbol::None),
+            )
+            .series(
+                Line::new()
+                    .name(&dataset.label)
+                    .data(points(|p| p[1]))
+                    .line_style(LineStyle::new().width(3).color(*line))
+                    .symbol(Symbol::Circle)
+                    .symbol_size(6)
+                    .item_style(ItemStyle::new().color(*line))
+                    .smooth(0.3),
+            );
+    }
+
+    if let Some(threshold) = sla_threshold {
+        chart = chart.series(
+            Line::new()
+                .name("SLA Threshold")
+                .mark_line(MarkLine::new()
+                    .data(vec![MarkLineVariant::Simple(
+                        MarkLineData::new()
+                            .y_axis(threshold)
+                            .name("SLA Threshold")
+                            .label(Label::new().formatter("SLA Threshold"))
+                    )])
+                    .line_style(LineStyle::new()
+                        .color("#ff006e")
+                        .width(2)
+                        .type_(LineStyleType::Dashed)))
+                .data(Vec::<f64>::new()),
+        );
+    }
+
+    chart
+}
+
 pub fn generate_combined_plots(
     latencies: &[Duration],
     timestamped_data: &[TimestampedLatency],
@@ -627,23 +1021,124 @@ pub fn generate_combined_plots(
     stats: &LatencyStats,
     window_minutes: Option<u64>,
     sla_threshold: Option<f64>,
+    assets: ReportAssets,
 ) -> Result<()> {
     let density_chart = build_density_chart(latencies, stats);
     let timeseries_chart = build_time_series_confidence_chart(timestamped_data, window_minutes, sla_threshold);
     let interactive_chart = build_interactive_time_series_chart(timestamped_data);
+    let phases_chart = build_phase_breakdown_chart(timestamped_data);
     
     let density_option = density_chart.to_string();
     let timeseries_option = timeseries_chart.to_string();
     let interactive_option = interactive_chart.to_string();
+    let phases_option = phases_chart.to_string();
     
     crate::latency::combined_plot::generate_combined_html(
         &density_option,
         &timeseries_option,
         &interactive_option,
+        &phases_option,
         stats,
         &output_path,
+        assets,
     )?;
     
     Ok(())
 }
 
+/// Renders several runs into one report, the first one is the baseline of the delta table.
+pub fn generate_comparison_plots(
+    datasets: &[LatencyDataset],
+    output_path: PathBuf,
+    window_minutes: Option<u64>,
+    sla_threshold: Option<f64>,
+    assets: ReportAssets,
+) -> Result<()> {
+    if datasets.len() < 2 {
+        anyhow::bail!("At least two datasets are needed for a comparison");
+    }
+
+    let mut runs = Vec::with_capacity(datasets.len());
+    for dataset in datasets {
+        let latencies: Vec<_> = dataset.data.iter().map(|d| d.latency).collect();
+        let stats = LatencyStats::compute(&latencies)
+            .with_context(|| format!("No successful samples in `{}`", dataset.label))?;
+        runs.push(crate::latency::combined_plot::ComparedRun {
+            label: &dataset.label,
+            samples: latencies.len(),
+            stats,
+        });
+    }
+
+    let density_option = build_density_comparison_chart(datasets).to_string();
+    let timeseries_option =
+        build_time_series_comparison_chart(datasets, window_minutes, sla_threshold).to_string();
+
+    crate::latency::combined_plot::generate_comparison_html(
+        &density_option,
+        &timeseries_option,
+        &runs,
+        &output_path,
+        assets,
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offset_buckets_align_runs() {
+        let run = |start: u64| -> Vec<TimestampedLatency> {
+            (0..4)
+                .map(|i| TimestampedLatency {
+                    timestamp: UNIX_EPOCH + Duration::from_secs(start + i * 30),
+                    latency: Duration::from_millis(100 * (i + 1)),
+                    phases: PhaseLatency::default(),
+                })
+                .collect()
+        };
+
+        let before = bucket_by_offset(&run(1_700_000_000), 60);
+        let after = bucket_by_offset(&run(1_700_086_417), 60);
+        let mut keys: Vec<_> = before.keys().copied().collect();
+        keys.sort();
+        assert_eq!(keys, vec![0, 60]);
+        assert_eq!(before, after);
+        assert_eq!(before[&60], vec![300.0, 400.0]);
+    }
+
+    #[test]
+    fn test_embedded_report_makes_no_requests() {
+        let data: Vec<_> = (0..10)
+            .map(|i| TimestampedLatency {
+                timestamp: UNIX_EPOCH + Duration::from_secs(1_700_000_000 + i),
+                latency: Duration::from_millis(100 + i * 10),
+                phases: PhaseLatency::default(),
+            })
+            .collect();
+        let latencies: Vec<_> = data.iter().map(|x| x.latency).collect();
+        let stats = LatencyStats::compute(&latencies).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.html");
+
+        generate_combined_plots(
+            &latencies,
+            &data,
+            path.clone(),
+            &stats,
+            None,
+            None,
+            ReportAssets::Embedded,
+        )
+        .unwrap();
+
+        // The inlined libraries mention `http://www.w3.org` namespaces, only loads matter.
+        let html = std::fs::read_to_string(&path).unwrap();
+        for load in ["src=\"http", "href=\"http", "url(http", "url(\"http", "@import"] {
+            assert!(!html.contains(load), "report loads a remote resource: {load}");
+        }
+    }
+}
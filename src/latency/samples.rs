@@ -0,0 +1,223 @@
+//! Per-transaction rows of the `latency --csv` output, read back by `plot`.
+//!
+//! Columns: `timestamp_ms,message_hash,lt,status,latency_ns,broadcast_ns,inclusion_ns,
+//! mc_lt_passed_ns,error`. `latency_ns` is the inclusion latency. Fields of phases that were not
+//! reached are empty, the error text is the last column so it may contain commas.
+
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use super::plotting::{PhaseLatency, TimestampedLatency};
+
+pub const CSV_HEADER: &str = concat!(
+    "timestamp_ms,message_hash,lt,status,latency_ns,",
+    "broadcast_ns,inclusion_ns,mc_lt_passed_ns,error"
+);
+const COLUMNS: usize = 9;
+
+/// How far a probe got, failed probes keep the phase they failed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    SendFailed,
+    Expired,
+    McLtPending,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::SendFailed => "send_failed",
+            Status::Expired => "expired",
+            Status::McLtPending => "mc_lt_pending",
+        }
+    }
+}
+
+impl FromStr for Status {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "ok" => Status::Ok,
+            "send_failed" => Status::SendFailed,
+            "expired" => Status::Expired,
+            "mc_lt_pending" => Status::McLtPending,
+            _ => anyhow::bail!("Unknown status `{s}`"),
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub timestamp: SystemTime,
+    pub message_hash: Option<String>,
+    pub lt: Option<u64>,
+    pub status: Status,
+    pub phases: Option<PhaseLatency>,
+    pub error: Option<String>,
+}
+
+impl Sample {
+    pub fn new(timestamp: SystemTime) -> Self {
+        Self {
+            timestamp,
+            message_hash: None,
+            lt: None,
+            status: Status::SendFailed,
+            phases: None,
+            error: None,
+        }
+    }
+
+    /// Successful samples in the form the charts use.
+    pub fn to_timestamped(&self) -> Option<TimestampedLatency> {
+        let phases = self.phases?;
+        Some(TimestampedLatency {
+            timestamp: self.timestamp,
+            latency: phases.inclusion,
+            phases,
+        })
+    }
+
+    pub fn write_csv<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let timestamp = self.timestamp.duration_since(UNIX_EPOCH)?.as_millis();
+        let nanos = |f: fn(&PhaseLatency) -> Duration| {
+            self.phases
+                .map(|p| f(&p).as_nanos().to_string())
+                .unwrap_or_default()
+        };
+        writeln!(
+            writer,
+            "{timestamp},{},{},{},{},{},{},{},{}",
+            self.message_hash.as_deref().unwrap_or_default(),
+            self.lt.map(|lt| lt.to_string()).unwrap_or_default(),
+            self.status.as_str(),
+            nanos(|p| p.inclusion),
+            nanos(|p| p.broadcast),
+            nanos(|p| p.inclusion),
+            nanos(|p| p.masterchain_lt_passed),
+            self.error
+                .as_deref()
+                .unwrap_or_default()
+                .replace(['\n', '\r'], " "),
+        )?;
+        Ok(())
+    }
+
+    fn parse_csv(line: &str) -> Result<Self> {
+        let fields: Vec<&str> = line.splitn(COLUMNS, ',').collect();
+        if fields.len() != COLUMNS {
+            anyhow::bail!("Expected {COLUMNS} columns, got {}", fields.len());
+        }
+        let optional = |field: &str| (!field.is_empty()).then(|| field.to_string());
+        let nanos = |field: &str| -> Result<Duration> {
+            Ok(Duration::from_nanos(
+                field.parse().context("Invalid duration")?,
+            ))
+        };
+
+        let phases = match fields[5..8] {
+            ["", "", ""] => None,
+            [broadcast, inclusion, masterchain_lt_passed] => Some(PhaseLatency {
+                broadcast: nanos(broadcast)?,
+                inclusion: nanos(inclusion)?,
+                masterchain_lt_passed: nanos(masterchain_lt_passed)?,
+            }),
+            _ => unreachable!(),
+        };
+        Ok(Self {
+            timestamp: UNIX_EPOCH
+                + Duration::from_millis(fields[0].parse().context("Invalid timestamp")?),
+            message_hash: optional(fields[1]),
+            lt: optional(fields[2])
+                .map(|lt| lt.parse())
+                .transpose()
+                .context("Invalid lt")?,
+            status: fields[3].parse()?,
+            phases,
+            error: optional(fields[8]),
+        })
+    }
+}
+
+pub fn read_csv(path: &Path) -> Result<Vec<Sample>> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open latency CSV {path:?}"))?;
+    let mut lines = std::io::BufReader::new(file).lines();
+
+    let header = lines.next().transpose()?.unwrap_or_default();
+    if header.trim() != CSV_HEADER {
+        anyhow::bail!("{path:?} is not a latency CSV of this version, header `{header}`");
+    }
+
+    let mut samples = Vec::new();
+    for (line_no, line) in lines.enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let sample =
+            Sample::parse_csv(&line).with_context(|| format!("{path:?}:{}", line_no + 2))?;
+        samples.push(sample);
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_roundtrip() {
+        let timestamp = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let ok = Sample {
+            timestamp,
+            message_hash: Some("ab".repeat(32)),
+            lt: Some(42),
+            status: Status::Ok,
+            phases: Some(PhaseLatency {
+                broadcast: Duration::from_millis(5),
+                inclusion: Duration::from_secs(1),
+                masterchain_lt_passed: Duration::from_millis(2500),
+            }),
+            error: None,
+        };
+        let failed = Sample {
+            status: Status::Expired,
+            error: Some("Message expired, retry\nlater".to_string()),
+            ..Sample::new(timestamp)
+        };
+
+        let mut data = format!("{CSV_HEADER}\n").into_bytes();
+        ok.write_csv(&mut data).unwrap();
+        failed.write_csv(&mut data).unwrap();
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), data).unwrap();
+
+        let samples = read_csv(file.path()).unwrap();
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].timestamp, timestamp);
+        assert_eq!(samples[0].lt, Some(42));
+        assert_eq!(samples[0].status, Status::Ok);
+        assert_eq!(
+            samples[0].to_timestamped().unwrap().latency,
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            samples[0].phases.unwrap().masterchain_lt_passed,
+            Duration::from_millis(2500)
+        );
+        assert_eq!(samples[1].status, Status::Expired);
+        assert!(samples[1].phases.is_none());
+        assert_eq!(
+            samples[1].error.as_deref(),
+            Some("Message expired, retry later")
+        );
+    }
+}
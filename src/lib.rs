@@ -1,3 +1,5 @@
+use std::ffi::OsString;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
@@ -7,35 +9,58 @@ use crate::send_tokens::SendTestArgs;
 use crate::swap::SwapTestArgs;
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
+use ed25519_dalek::Keypair;
 use everscale_rpc_client::{ClientOptions, ReliabilityParams, RpcClient};
+use report::{RunReport, RunStats};
+use serde::Serialize;
 use url::Url;
 
+pub use sla::SlaViolation;
+
 mod abi;
 mod app_cache;
+mod archive;
 mod build_payload;
 pub mod latency;
+mod metrics;
+#[cfg(feature = "mock-node")]
+pub mod mock_node;
 mod models;
+mod preflight;
+mod provision;
+mod report;
 mod send;
 
 mod rand_send;
 
 mod dag;
+mod deployments;
 mod dos;
+mod endpoints;
+mod replay;
+mod scenario;
 mod send_to_targets;
 mod send_tokens;
+mod sla;
 mod swap;
+mod sweep;
 #[cfg(test)]
 mod test_chart_series;
+mod tracker;
 mod util;
+mod wallets;
 
-#[derive(Parser, Debug, Clone)]
+/// Serialized into the report as the common options of the run, see [`RunReport::new`].
+#[derive(Parser, Debug, Clone, Serialize)]
 pub(crate) struct Args {
     #[command(subcommand)]
+    #[serde(skip)]
     command: Commands,
     #[clap(short, long)]
     project_root: PathBuf,
 
     #[clap(short, long)]
+    #[serde(serialize_with = "report::serialize_redacted_urls")]
     endpoints: Vec<Url>,
 
     /// seed for rng
@@ -54,9 +79,88 @@ pub(crate) struct Args {
     /// Select the network-specific deployment directory under <project_root>/deployments/
     #[clap(long)]
     network: Option<String>,
+
+    /// Role to deployment mapping, defaults to `deployments.toml` in the deployment directory
+    #[clap(long)]
+    deployments_manifest: Option<PathBuf>,
+
+    /// Write a JSON report of the run to this path
+    #[clap(long)]
+    report: Option<PathBuf>,
+
+    /// Build and sign messages without broadcasting, storing them into this archive for `replay`
+    #[clap(long)]
+    record: Option<PathBuf>,
+
+    /// Expiration in seconds of recorded messages, they must still be valid when replayed
+    #[clap(long, default_value = "3600")]
+    record_ttl: u64,
+
+    /// Refuse to start transfer tests whose estimated spend exceeds this many nanotons
+    #[clap(long)]
+    max_spend: Option<u64>,
+
+    /// Serve Prometheus metrics on this address, e.g. `0.0.0.0:9100`
+    #[clap(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Use a client per endpoint and report requests, latency and errors of every endpoint,
+    /// implied by `--metrics-addr`
+    #[clap(long)]
+    endpoint_stats: bool,
+
+    /// Sender wallet contract of the transfer tests
+    #[clap(long, value_enum, default_value = "ever-wallet")]
+    wallet_type: wallets::WalletKind,
+
+    /// Sender wallets as `<wallet-type> <account index>` or `<wallet-type> key:<secret hex>`
+    /// lines, overrides `--wallet-type`
+    #[clap(long)]
+    wallets_file: Option<PathBuf>,
+
+    /// Generate new EverWallet keys for the senders and fund them from the sender wallet, the
+    /// first transfer of every wallet deploys it
+    #[clap(long, conflicts_with_all = ["wallets_file", "wallet_type"])]
+    fresh_wallets: bool,
+
+    /// Nanotons sent to every fresh wallet, must cover its deployment and share of the test
+    #[clap(long, default_value = "10000000000")]
+    fresh_wallet_balance: u64,
+
+    /// Where the keys of fresh wallets are stored for `sweep --wallets-file`, defaults to
+    /// `fresh-wallets-<unix time>.keys` in the project root
+    #[clap(long, requires = "fresh_wallets")]
+    fresh_wallets_keys: Option<PathBuf>,
+
+    /// Attach a `nekroddos:<run>:<instance>:<wallet>:<seq>` comment to plain transfers
+    #[clap(long)]
+    comment: bool,
+
+    /// Run id of the transfer comments, random by default
+    #[clap(long, requires = "comment")]
+    run_id: Option<String>,
+
+    /// Tells generators apart when several instances share a run id
+    #[clap(long, default_value = "0", requires = "run_id")]
+    instance_id: String,
+
+    /// Look up the transaction of every sent message and report delivered, failed and expired
+    /// counts, costs one extra request per pending message every poll
+    #[clap(long, conflicts_with = "record")]
+    track_messages: bool,
+
+    /// SLA rule checked at the end of the run, e.g. `p95<2s`, `error_rate<0.5%` or `rps>=95%`
+    /// of the target, repeatable. A broken rule makes the process exit with code 3
+    #[clap(long = "sla", value_name = "RULE")]
+    sla_rules: Vec<sla::Rule>,
+
+    /// Let `--sla` rules pass when the run has no data for them instead of failing them
+    #[clap(long, requires = "sla_rules")]
+    sla_allow_missing: bool,
 }
 
-#[derive(Subcommand, Debug, Clone)]
+#[derive(Subcommand, Debug, Clone, Serialize)]
+#[serde(untagged)]
 enum Commands {
     Swap(SwapTestArgs),
     Dag(DagTestArgs),
@@ -65,11 +169,62 @@ enum Commands {
     RandSend(rand_send::RandSendTestArgs),
     SendToTargets(send_to_targets::SendToTargetsArgs),
     AccountsDos(dos::DosTestArgs),
+    /// Run several subcommands as ordered or concurrent phases from a TOML/YAML file
+    Scenario(scenario::ScenarioArgs),
+    /// Broadcast messages recorded with `--record`
+    Replay(replay::ReplayArgs),
+    /// Inspect deployments resolved for the selected network
+    Deployments(deployments::DeploymentsArgs),
+    /// Deploy and fund the contracts the tests rely on
+    Provision(provision::ProvisionArgs),
+    /// Send the remaining balance of the factory wallets back to a collector
+    Sweep(sweep::SweepArgs),
+    /// Rebuild the latency HTML report, or compare runs, from saved `latency --csv` files
+    Plot(latency::PlotArgs),
+}
+
+impl Commands {
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::Swap(_) => "swap",
+            Commands::Dag(_) => "dag",
+            Commands::Send(_) => "send",
+            Commands::Latency(_) => "latency",
+            Commands::RandSend(_) => "rand-send",
+            Commands::SendToTargets(_) => "send-to-targets",
+            Commands::AccountsDos(_) => "accounts-dos",
+            Commands::Scenario(_) => "scenario",
+            Commands::Replay(_) => "replay",
+            Commands::Deployments(_) => "deployments",
+            Commands::Provision(_) => "provision",
+            Commands::Sweep(_) => "sweep",
+            Commands::Plot(_) => "plot",
+        }
+    }
 }
 
 pub async fn run_test() -> Result<()> {
     env_logger::init();
-    let app_args = Args::parse();
+    run(Args::parse()).await
+}
+
+/// Same as [`run_test`] but with an explicit command line, the first item is the binary name.
+pub async fn run_from<I, T>(args: I) -> Result<()>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    run(Args::try_parse_from(args)?).await
+}
+
+async fn run(app_args: Args) -> Result<()> {
+    // Works on local files only, needs neither keys nor endpoints.
+    if let Commands::Plot(args) = &app_args.command {
+        if !app_args.sla_rules.is_empty() {
+            anyhow::bail!("`--sla` checks a run, `plot` has none");
+        }
+        return latency::plot(args.clone());
+    }
 
     dotenvy::from_filename(app_args.project_root.join(".env"))
         .context("Failed to load .env file")?;
@@ -79,41 +234,136 @@ pub async fn run_test() -> Result<()> {
         nekoton::crypto::derive_from_phrase(&seed, nekoton::crypto::MnemonicType::Labs(0))
             .context("Failed to derive keypair")?;
     let keypair = Arc::new(keypair);
-    let client = RpcClient::new(
-        app_args.endpoints.clone(),
-        ClientOptions {
-            request_timeout: Duration::from_secs(60),
-            choose_strategy: everscale_rpc_client::ChooseStrategy::RoundRobin,
-            reliability_params: ReliabilityParams {
-                mc_acceptable_time_diff_sec: app_args.node_is_dead_seconds,
-                sc_acceptable_time_diff_sec: app_args.node_is_dead_seconds,
-            },
-            ..Default::default()
+    let client_options = || ClientOptions {
+        request_timeout: Duration::from_secs(60),
+        choose_strategy: everscale_rpc_client::ChooseStrategy::RoundRobin,
+        reliability_params: ReliabilityParams {
+            mc_acceptable_time_diff_sec: app_args.node_is_dead_seconds,
+            sc_acceptable_time_diff_sec: app_args.node_is_dead_seconds,
         },
-    )
-    .await?;
+        ..Default::default()
+    };
+    let client = RpcClient::new(app_args.endpoints.clone(), client_options()).await?;
+    let mut clients = Vec::new();
+    if app_args.endpoint_stats || app_args.metrics_addr.is_some() {
+        for endpoint in &app_args.endpoints {
+            match RpcClient::new(vec![endpoint.clone()], client_options()).await {
+                Ok(endpoint_client) => clients.push((endpoint.clone(), endpoint_client)),
+                Err(e) => log::warn!("Leaving {endpoint} out of the endpoint stats: {e:#}"),
+            }
+        }
+    }
+    endpoints::start(clients);
+
+    if let Some(path) = &app_args.record {
+        archive::start_recording(path, Duration::from_secs(app_args.record_ttl))?;
+    }
+    if let Some(addr) = app_args.metrics_addr {
+        metrics::serve(addr).await?;
+    }
+    if app_args.track_messages {
+        tracker::start(client.clone());
+    }
+    if app_args.comment {
+        let run_id = app_args
+            .run_id
+            .clone()
+            .unwrap_or_else(|| format!("{:08x}", rand::random::<u32>()));
+        send::enable_comments(run_id, app_args.instance_id.clone())?;
+    }
+
+    let stats = RunStats::new();
+    let mut report = RunReport::new(&app_args);
+    let report_path = app_args.report.clone();
+    let sla_rules = app_args.sla_rules.clone();
+    let sla_allow_missing = app_args.sla_allow_missing;
+
+    let result = match app_args.command.clone() {
+        Commands::Scenario(args) => {
+            scenario::run(args, app_args, keypair, client, &mut report).await
+        }
+        command => run_command(command, app_args, keypair, client, stats.clone()).await,
+    };
+    let result = result.and(archive::finish_recording().await);
+    report.outcomes = tracker::finish(archive::message_ttl() + Duration::from_secs(30)).await;
+
+    report.finish(&stats, &result);
+    if report.counts.duplicates > 0 {
+        log::warn!(
+            "{} duplicate messages were detected and not broadcast",
+            report.counts.duplicates
+        );
+    }
+    report.endpoint_stats = endpoints::report();
+    endpoints::print_table(&report.endpoint_stats);
+    let sla_result = sla::check(&sla_rules, sla_allow_missing, &mut report);
+    if let Some(path) = report_path {
+        report.write(&path)?;
+    }
 
-    match &app_args.command {
+    result.and(sla_result)
+}
+
+async fn run_command(
+    command: Commands,
+    app_args: Args,
+    keypair: Arc<Keypair>,
+    client: RpcClient,
+    stats: RunStats,
+) -> Result<()> {
+    if archive::is_recording()
+        && matches!(
+            command,
+            Commands::Latency(_)
+                | Commands::AccountsDos(_)
+                | Commands::Replay(_)
+                | Commands::Provision(_)
+                | Commands::Sweep(_)
+        )
+    {
+        anyhow::bail!("`{}` can't be recorded", command.name());
+    }
+    metrics::register(command.name(), &stats);
+
+    match command {
         Commands::Swap(args) => {
-            swap::run(args.clone(), app_args, &keypair, client).await?;
+            swap::run(args, app_args, &keypair, client, stats).await?;
         }
         Commands::Dag(args) => {
-            dag::run(args.clone(), app_args, client).await?;
+            dag::run(args, app_args, client, stats).await?;
         }
         Commands::Send(args) => {
-            send_tokens::run(args.clone(), app_args, keypair, client).await?;
+            send_tokens::run(args, app_args, keypair, client, stats).await?;
         }
         Commands::Latency(args) => {
-            latency::run(args.clone(), app_args, &keypair, client).await?;
+            latency::run(args, app_args, keypair, client, stats).await?;
         }
         Commands::RandSend(arg) => {
-            rand_send::run(arg.clone(), app_args, keypair, client).await?;
+            rand_send::run(arg, app_args, keypair, client, stats).await?;
         }
         Commands::SendToTargets(args) => {
-            send_to_targets::run(args.clone(), app_args, keypair, client).await?;
+            send_to_targets::run(args, app_args, keypair, client, stats).await?;
         }
         Commands::AccountsDos(args) => {
-            dos::run(args.clone(), app_args, client).await?;
+            dos::run(args, app_args, client, stats).await?;
+        }
+        Commands::Replay(args) => {
+            replay::run(args, app_args, client, stats).await?;
+        }
+        Commands::Deployments(args) => {
+            deployments::run(args, app_args, client).await?;
+        }
+        Commands::Provision(args) => {
+            provision::run(args, app_args, keypair, client, stats).await?;
+        }
+        Commands::Sweep(args) => {
+            sweep::run(args, app_args, keypair, client, stats).await?;
+        }
+        Commands::Scenario(_) => {
+            anyhow::bail!("Nested scenarios are not supported");
+        }
+        Commands::Plot(args) => {
+            latency::plot(args)?;
         }
     }
 
@@ -1,7 +1,17 @@
 use anyhow::Result;
-use nekroddos::run_test;
+use nekroddos::{run_test, SlaViolation};
+
+/// Exit code of runs that finished but broke an `--sla` rule, errors exit with 1 and clap usage
+/// errors with 2.
+const SLA_EXIT_CODE: i32 = 3;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    run_test().await
+    match run_test().await {
+        Err(e) if e.is::<SlaViolation>() => {
+            eprintln!("Error: {e}");
+            std::process::exit(SLA_EXIT_CODE);
+        }
+        result => result,
+    }
 }
@@ -0,0 +1,331 @@
+//! Prometheus exporter enabled with `--metrics-addr`.
+//!
+//! Every run registers its [`RunStats`] here, metrics are rendered from them on scrape and
+//! labelled with the subcommand, so concurrent scenario steps are told apart.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+
+use crate::endpoints::{self, EndpointReport};
+use crate::report::RunStats;
+
+/// Upper bounds of the exported latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 14] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0,
+];
+
+static RUNS: Mutex<Vec<(&'static str, RunStats)>> = Mutex::new(Vec::new());
+
+/// Makes the run visible to the exporter. Finished runs stay registered so counters never go
+/// back, gauges only cover running ones.
+pub fn register(command: &'static str, stats: &RunStats) {
+    RUNS.lock().unwrap().push((command, stats.clone()));
+}
+
+/// Requests are attributed to endpoints by [`endpoints`], which the caller starts along.
+pub async fn serve(addr: SocketAddr) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind metrics server to {addr}"))?;
+    let router = Router::new().route("/metrics", get(handle_metrics));
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, router).await {
+            log::error!("Metrics server stopped: {e:?}");
+        }
+    });
+
+    log::info!("Serving metrics on http://{addr}/metrics");
+    Ok(())
+}
+
+async fn handle_metrics() -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(&RUNS.lock().unwrap()) + &render_endpoints(&endpoints::report()),
+    )
+}
+
+#[derive(Default)]
+struct CommandMetrics {
+    built: u64,
+    sent: u64,
+    confirmed: u64,
+    failed: u64,
+    transfers: u64,
+    duplicates: u64,
+    target_rps: u64,
+    achieved_rps: u64,
+    /// Successful and failed requests by method, for subcommands issuing several kinds.
+    methods: BTreeMap<&'static str, (u64, u64)>,
+    /// Cumulative counts for [`LATENCY_BUCKETS`] plus `+Inf`.
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    latency_sum: f64,
+}
+
+impl CommandMetrics {
+    fn add(&mut self, stats: &RunStats) {
+        self.built += stats.built.load(Ordering::Relaxed);
+        self.sent += stats.sent.load(Ordering::Relaxed);
+        self.confirmed += stats.confirmed.load(Ordering::Relaxed);
+        self.failed += stats.failed.load(Ordering::Relaxed);
+        self.transfers += stats.transfers.load(Ordering::Relaxed);
+        self.duplicates += stats.duplicates();
+        if stats.is_running() {
+            self.target_rps += stats.target_rps.load(Ordering::Relaxed);
+            self.achieved_rps += stats.achieved_rps();
+        }
+        for (name, method) in stats.methods() {
+            let counts = self.methods.entry(name).or_default();
+            counts.0 += method.ok.load(Ordering::Relaxed);
+            counts.1 += method.failed.load(Ordering::Relaxed);
+        }
+
+        for bucket in &stats.latency.load() {
+            let count = bucket.count();
+            if count == 0 {
+                continue;
+            }
+            let end = bucket.end() as f64 / 1e9;
+            let mid = (bucket.start() as f64 + bucket.end() as f64) / 2e9;
+            self.latency_sum += mid * count as f64;
+
+            let first = LATENCY_BUCKETS
+                .iter()
+                .position(|le| end <= *le)
+                .unwrap_or(LATENCY_BUCKETS.len());
+            for cumulative in &mut self.buckets[first..] {
+                *cumulative += count;
+            }
+        }
+    }
+}
+
+fn render(runs: &[(&'static str, RunStats)]) -> String {
+    let mut by_command: Vec<(&str, CommandMetrics)> = Vec::new();
+    for (command, stats) in runs {
+        let idx = match by_command.iter().position(|(c, _)| c == command) {
+            Some(idx) => idx,
+            None => {
+                by_command.push((command, CommandMetrics::default()));
+                by_command.len() - 1
+            }
+        };
+        by_command[idx].1.add(stats);
+    }
+
+    let mut out = String::new();
+    let simple_metrics: [(&str, &str, &str, fn(&CommandMetrics) -> u64); 8] = [
+        (
+            "nekroddos_messages_built_total",
+            "Messages built and handed over for broadcast",
+            "counter",
+            |m| m.built,
+        ),
+        (
+            "nekroddos_messages_sent_total",
+            "Messages accepted by the node",
+            "counter",
+            |m| m.sent,
+        ),
+        (
+            "nekroddos_messages_failed_total",
+            "Messages that failed to send",
+            "counter",
+            |m| m.failed,
+        ),
+        (
+            "nekroddos_messages_confirmed_total",
+            "Messages confirmed on chain",
+            "counter",
+            |m| m.confirmed,
+        ),
+        (
+            "nekroddos_transfers_total",
+            "Internal transfers carried by sent messages",
+            "counter",
+            |m| m.transfers,
+        ),
+        (
+            "nekroddos_duplicate_messages_total",
+            "Messages dropped because an identical one was already sent",
+            "counter",
+            |m| m.duplicates,
+        ),
+        ("nekroddos_target_rps", "Requested rate", "gauge", |m| {
+            m.target_rps
+        }),
+        (
+            "nekroddos_achieved_rps",
+            "Messages sent during the last second",
+            "gauge",
+            |m| m.achieved_rps,
+        ),
+    ];
+    for (name, help, kind, value) in simple_metrics {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for (command, metrics) in &by_command {
+            let _ = writeln!(out, "{name}{{command=\"{command}\"}} {}", value(metrics));
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP nekroddos_rpc_requests_total Requests by method of subcommands mixing several"
+    );
+    let _ = writeln!(out, "# TYPE nekroddos_rpc_requests_total counter");
+    for (command, metrics) in &by_command {
+        for (method, (ok, failed)) in &metrics.methods {
+            for (result, count) in [("ok", ok), ("failed", failed)] {
+                let _ = writeln!(
+                    out,
+                    "nekroddos_rpc_requests_total{{command=\"{command}\",method=\"{method}\",\
+                    result=\"{result}\"}} {count}"
+                );
+            }
+        }
+    }
+
+    let name = "nekroddos_latency_seconds";
+    let _ = writeln!(
+        out,
+        "# HELP {name} Per-message latency: broadcast round-trip, confirmation or request time"
+    );
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    for (command, metrics) in &by_command {
+        for (le, count) in LATENCY_BUCKETS.iter().zip(&metrics.buckets) {
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{command=\"{command}\",le=\"{le}\"}} {count}"
+            );
+        }
+        let total = metrics.buckets[LATENCY_BUCKETS.len()];
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{command=\"{command}\",le=\"+Inf\"}} {total}"
+        );
+        let _ = writeln!(
+            out,
+            "{name}_sum{{command=\"{command}\"}} {}",
+            metrics.latency_sum
+        );
+        let _ = writeln!(out, "{name}_count{{command=\"{command}\"}} {total}");
+    }
+
+    out
+}
+
+fn render_endpoints(endpoints: &BTreeMap<String, EndpointReport>) -> String {
+    let mut out = String::new();
+    if endpoints.is_empty() {
+        return out;
+    }
+
+    let name = "nekroddos_endpoint_requests_total";
+    let _ = writeln!(out, "# HELP {name} Requests by the endpoint that served them");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    for (endpoint, report) in endpoints {
+        for (result, count) in [("ok", report.ok), ("failed", report.failed)] {
+            let _ = writeln!(
+                out,
+                "{name}{{endpoint=\"{endpoint}\",result=\"{result}\"}} {count}"
+            );
+        }
+    }
+
+    let name = "nekroddos_rpc_errors_total";
+    let _ = writeln!(out, "# HELP {name} Failed requests by endpoint and error class");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    for (endpoint, report) in endpoints {
+        for (class, count) in &report.errors {
+            let _ = writeln!(
+                out,
+                "{name}{{endpoint=\"{endpoint}\",class=\"{class}\"}} {count}"
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_render() {
+        let stats = RunStats::new();
+        stats.record_built();
+        stats.record_built();
+        stats.record_result::<(), ()>(Duration::from_millis(30), &Ok(()));
+        stats.record_result::<(), ()>(Duration::from_millis(30), &Err(()));
+        stats.record_transfers(4);
+        stats.set_target_rps(100);
+        stats.method("getContractState").record(Duration::from_millis(5), false);
+
+        let runs = [("send", stats.clone()), ("send", stats.clone())];
+        let text = render(&runs);
+        assert!(text.contains("nekroddos_messages_built_total{command=\"send\"} 4"));
+        assert!(text.contains("nekroddos_messages_sent_total{command=\"send\"} 2"));
+        assert!(text.contains("nekroddos_messages_failed_total{command=\"send\"} 2"));
+        assert!(text.contains("nekroddos_transfers_total{command=\"send\"} 8"));
+        assert!(text.contains("nekroddos_target_rps{command=\"send\"} 200"));
+        assert!(text.contains(
+            "nekroddos_rpc_requests_total{command=\"send\",method=\"getContractState\",\
+            result=\"failed\"} 2"
+        ));
+        assert!(text.contains("nekroddos_latency_seconds_bucket{command=\"send\",le=\"0.025\"} 0"));
+        assert!(text.contains("nekroddos_latency_seconds_bucket{command=\"send\",le=\"0.05\"} 2"));
+        assert!(text.contains("nekroddos_latency_seconds_bucket{command=\"send\",le=\"+Inf\"} 2"));
+
+        // Finished runs keep their counters but leave the gauges.
+        stats.stop();
+        let text = render(&runs);
+        assert!(text.contains("nekroddos_messages_sent_total{command=\"send\"} 2"));
+        assert!(text.contains("nekroddos_target_rps{command=\"send\"} 0"));
+
+        // Concurrent runs count only their own duplicates, a finished run no longer grows.
+        let duplicate = || -> anyhow::Result<()> {
+            let from = format!("0:{:064x}", 1).parse().unwrap();
+            Err(crate::send::DuplicateMessage { from }.into())
+        };
+        let other = RunStats::new();
+        stats.record_send(Duration::from_millis(1), &duplicate());
+        other.record_send(Duration::from_millis(1), &duplicate());
+        other.record_send(Duration::from_millis(1), &duplicate());
+        assert_eq!((stats.duplicates(), other.duplicates()), (1, 2));
+        let runs = [("send", stats.clone()), ("send", other.clone())];
+        let text = render(&runs);
+        assert!(text.contains("nekroddos_duplicate_messages_total{command=\"send\"} 3"));
+    }
+
+    #[test]
+    fn test_render_endpoints() {
+        assert!(render_endpoints(&BTreeMap::new()).is_empty());
+
+        let endpoint = EndpointReport {
+            ok: 7,
+            failed: 3,
+            errors: BTreeMap::from([("timeout".to_string(), 2), ("http_503".to_string(), 1)]),
+            latency: None,
+        };
+        let text = render_endpoints(&BTreeMap::from([("http://a/".to_string(), endpoint)]));
+        for line in [
+            r#"nekroddos_endpoint_requests_total{endpoint="http://a/",result="ok"} 7"#,
+            r#"nekroddos_endpoint_requests_total{endpoint="http://a/",result="failed"} 3"#,
+            r#"nekroddos_rpc_errors_total{endpoint="http://a/",class="timeout"} 2"#,
+            r#"nekroddos_rpc_errors_total{endpoint="http://a/",class="http_503"} 1"#,
+        ] {
+            assert!(text.contains(line), "missing {line} in:\n{text}");
+        }
+    }
+}
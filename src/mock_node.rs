@@ -0,0 +1,328 @@
+//! In-process JSON-RPC node for running subcommands without a live network.
+//!
+//! Serves the subset of the jrpc protocol the tool uses (`getTimings`, `getContractState`,
+//! `sendMessage`, `getBlockchainConfig`, `getAccountsByCodeHash`, `getDstTransaction`,
+//! `getTransactionsList`) from in-memory account states. An external message sent to an account
+//! is "executed" by bumping its `last_trans_lt` and recording an empty transaction for its hash,
+//! which is enough for the confirmation loops of the subcommands.
+//!
+//! Fixture directory layout for [`MockNode::load_fixtures`]:
+//!
+//! ```text
+//! accounts/*.boc  serialized `Account`s, `latency` also needs the config contract
+//! config.boc      serialized `ConfigParams`, required by transfer subcommands
+//! global_id       network global id, decimal, defaults to 42
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result};
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use nekoton::transport::models::{ExistingContract, RawContractState};
+use nekoton_abi::{GenTimings, LastTransactionId, TransactionId};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use ton_block::{
+    Account, AccountState, AccountStuff, Deserializable, Message, MsgAddressInt, Serializable,
+    Transaction,
+};
+use ton_types::{Cell, UInt256};
+use url::Url;
+
+const DEFAULT_GLOBAL_ID: i32 = 42;
+
+#[derive(Default)]
+struct MockState {
+    accounts: BTreeMap<MsgAddressInt, AccountStuff>,
+    config: Option<(Cell, i32)>,
+    sent_messages: Vec<Message>,
+    requests: HashMap<String, usize>,
+}
+
+pub struct MockNode {
+    state: Arc<Mutex<MockState>>,
+    addr: SocketAddr,
+    server: tokio::task::JoinHandle<()>,
+}
+
+impl MockNode {
+    /// Starts the server on a random localhost port.
+    pub async fn start() -> Result<Self> {
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .context("Failed to bind mock node")?;
+        let addr = listener.local_addr()?;
+
+        let router = Router::new()
+            .route("/rpc", post(handle_request))
+            .with_state(state.clone());
+        let server = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, router).await {
+                log::error!("Mock node stopped: {e:?}");
+            }
+        });
+
+        log::info!("Mock node listening on {addr}");
+        Ok(Self {
+            state,
+            addr,
+            server,
+        })
+    }
+
+    pub fn url(&self) -> Url {
+        Url::parse(&format!("http://{}/rpc", self.addr)).expect("valid url")
+    }
+
+    pub fn load_fixtures(&self, dir: &Path) -> Result<()> {
+        let accounts_dir = dir.join("accounts");
+        if accounts_dir.is_dir() {
+            for entry in std::fs::read_dir(&accounts_dir)? {
+                let path = entry?.path();
+                if path.extension().map(|e| e == "boc").unwrap_or(false) {
+                    let account = Account::construct_from_bytes(&std::fs::read(&path)?)
+                        .with_context(|| format!("Invalid account fixture {path:?}"))?;
+                    self.insert_account(account)?;
+                }
+            }
+        }
+
+        let config_path = dir.join("config.boc");
+        if config_path.is_file() {
+            let config = ton_types::deserialize_tree_of_cells(&mut std::fs::File::open(
+                &config_path,
+            )?)
+            .context("Invalid config fixture")?;
+            let global_id = match std::fs::read_to_string(dir.join("global_id")) {
+                Ok(s) => s.trim().parse().context("Invalid global_id fixture")?,
+                Err(_) => DEFAULT_GLOBAL_ID,
+            };
+            self.set_config(config, global_id);
+        }
+
+        Ok(())
+    }
+
+    pub fn insert_account(&self, account: Account) -> Result<()> {
+        let Account::Account(stuff) = account else {
+            anyhow::bail!("Empty account fixture");
+        };
+        self.state
+            .lock()
+            .unwrap()
+            .accounts
+            .insert(stuff.addr.clone(), stuff);
+        Ok(())
+    }
+
+    pub fn set_config(&self, config: Cell, global_id: i32) {
+        self.state.lock().unwrap().config = Some((config, global_id));
+    }
+
+    pub fn account(&self, address: &MsgAddressInt) -> Option<AccountStuff> {
+        self.state.lock().unwrap().accounts.get(address).cloned()
+    }
+
+    pub fn sent_messages(&self) -> Vec<Message> {
+        self.state.lock().unwrap().sent_messages.clone()
+    }
+
+    /// Number of served requests of the given jrpc method.
+    pub fn request_count(&self, method: &str) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .requests
+            .get(method)
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for MockNode {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+#[derive(Deserialize)]
+struct JrpcRequest {
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+async fn handle_request(
+    State(state): State<Arc<Mutex<MockState>>>,
+    Json(request): Json<JrpcRequest>,
+) -> Json<Value> {
+    let mut state = state.lock().unwrap();
+    *state.requests.entry(request.method.clone()).or_default() += 1;
+
+    let result = match request.method.as_str() {
+        "getTimings" => Ok(get_timings()),
+        "getContractState" => get_contract_state(&state, &request.params),
+        "sendMessage" => send_message(&mut state, &request.params),
+        "getBlockchainConfig" => get_blockchain_config(&state),
+        "getAccountsByCodeHash" => get_accounts_by_code_hash(&state, &request.params),
+        "getDstTransaction" => get_dst_transaction(&state, &request.params),
+        // Executed messages leave no transactions behind, only their hashes are known.
+        "getTransactionsList" => Ok(json!([])),
+        method => Err((-32601, format!("Method not found: {method}"))),
+    };
+
+    Json(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": request.id, "result": result }),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": request.id,
+            "error": { "code": code, "message": message },
+        }),
+    })
+}
+
+type MethodResult = std::result::Result<Value, (i32, String)>;
+
+fn invalid_params(e: impl std::fmt::Display) -> (i32, String) {
+    (-32602, format!("Invalid params: {e}"))
+}
+
+fn param<'a>(params: &'a Value, name: &str) -> std::result::Result<&'a str, (i32, String)> {
+    params
+        .get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid_params(format!("missing `{name}`")))
+}
+
+fn now_sec() -> u32 {
+    nekoton_utils::now_sec_u64() as u32
+}
+
+fn get_timings() -> Value {
+    json!({
+        "lastMcBlockSeqno": 1,
+        "lastShardClientMcBlockSeqno": 1,
+        "lastMcUtime": now_sec(),
+        "mcTimeDiff": 0,
+        "shardClientTimeDiff": 0,
+        "smallestKnownLt": null,
+    })
+}
+
+fn get_contract_state(state: &MockState, params: &Value) -> MethodResult {
+    let address = MsgAddressInt::from_str(param(params, "address")?).map_err(invalid_params)?;
+
+    let contract = match state.accounts.get(&address) {
+        Some(account) => RawContractState::Exists(ExistingContract {
+            account: account.clone(),
+            timings: GenTimings::Known {
+                gen_lt: gen_lt(state, account),
+                gen_utime: now_sec(),
+            },
+            last_transaction_id: LastTransactionId::Exact(TransactionId {
+                lt: account.storage.last_trans_lt,
+                hash: UInt256::default(),
+            }),
+        }),
+        None => RawContractState::NotExists {
+            timings: GenTimings::Unknown,
+        },
+    };
+
+    serde_json::to_value(contract).map_err(|e| (-32603, e.to_string()))
+}
+
+/// Masterchain states come from a block past every transaction, so sent messages are final.
+fn gen_lt(state: &MockState, account: &AccountStuff) -> u64 {
+    if account.addr.workchain_id() != -1 {
+        return account.storage.last_trans_lt;
+    }
+    let last_lt = state.accounts.values().map(|a| a.storage.last_trans_lt);
+    last_lt.max().unwrap_or_default() + 1
+}
+
+fn send_message(state: &mut MockState, params: &Value) -> MethodResult {
+    let boc = BASE64
+        .decode(param(params, "message")?)
+        .map_err(invalid_params)?;
+    let message = Message::construct_from_bytes(&boc).map_err(invalid_params)?;
+
+    if let Some(dst) = message.dst() {
+        if let Some(account) = state.accounts.get_mut(&dst) {
+            account.storage.last_trans_lt += 1;
+        }
+    }
+    state.sent_messages.push(message);
+
+    Ok(Value::Null)
+}
+
+fn get_dst_transaction(state: &MockState, params: &Value) -> MethodResult {
+    let requested = param(params, "messageHash")?;
+    let known = state.sent_messages.iter().any(|message| {
+        message.hash().is_ok_and(|hash| {
+            requested == hash.to_hex_string() || requested == BASE64.encode(hash.as_slice())
+        })
+    });
+    if !known {
+        return Ok(Value::Null);
+    }
+
+    let boc = Transaction::default()
+        .write_to_bytes()
+        .map_err(|e| (-32603, e.to_string()))?;
+    Ok(Value::String(BASE64.encode(boc)))
+}
+
+fn get_blockchain_config(state: &MockState) -> MethodResult {
+    let (config, global_id) = state
+        .config
+        .as_ref()
+        .ok_or_else(|| (-32603, "No config fixture loaded".to_string()))?;
+    let boc = ton_types::serialize_toc(config).map_err(|e| (-32603, e.to_string()))?;
+
+    Ok(json!({
+        "globalId": global_id,
+        "config": BASE64.encode(boc),
+        "seqno": 1,
+    }))
+}
+
+fn get_accounts_by_code_hash(state: &MockState, params: &Value) -> MethodResult {
+    let code_hash = UInt256::from_str(param(params, "codeHash")?).map_err(invalid_params)?;
+    let continuation = match params.get("continuation").and_then(Value::as_str) {
+        Some(addr) => Some(MsgAddressInt::from_str(addr).map_err(invalid_params)?),
+        None => None,
+    };
+    let limit = params.get("limit").and_then(Value::as_u64).unwrap_or(50) as usize;
+
+    let addresses: Vec<String> = state
+        .accounts
+        .iter()
+        .filter(|(addr, _)| continuation.as_ref().map(|c| *addr > c).unwrap_or(true))
+        .filter(|(_, account)| code_hash_of(account) == Some(code_hash))
+        .take(limit)
+        .map(|(addr, _)| addr.to_string())
+        .collect();
+
+    Ok(json!(addresses))
+}
+
+fn code_hash_of(account: &AccountStuff) -> Option<UInt256> {
+    match &account.storage.state {
+        AccountState::AccountActive { state_init } => {
+            state_init.code.as_ref().map(|code| code.repr_hash())
+        }
+        _ => None,
+    }
+}
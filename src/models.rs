@@ -137,12 +137,6 @@ impl SendData {
     }
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct EverWalletInfo {
-    #[serde(with = "serde_address")]
-    pub address: MsgAddressInt,
-}
-
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GenericDeploymentInfo {
     #[serde(with = "serde_address")]
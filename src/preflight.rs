@@ -0,0 +1,218 @@
+//! Pre-flight spend estimation for transfer subcommands.
+//!
+//! Fees are derived from the basechain gas and forward prices of the current blockchain config
+//! and typical EverWallet transfer sizes, so the estimate is an upper bound rather than exact.
+
+use anyhow::{Context, Result};
+use everscale_rpc_client::RpcClient;
+use ton_block::{GasLimitsPrices, MsgAddressInt, MsgForwardPrices};
+
+use crate::provision::{fetch_balances, AccountBalance};
+use crate::Args;
+
+/// Gas used by an EverWallet `sendTransaction` with a single outbound message.
+const TRANSFER_GAS: u64 = 8_000;
+/// Signed external message carrying a transfer with a small payload.
+const EXTERNAL_MESSAGE_BITS: u64 = 2_000;
+const EXTERNAL_MESSAGE_CELLS: u64 = 4;
+/// Outbound internal message with a small payload.
+const INTERNAL_MESSAGE_BITS: u64 = 1_500;
+const INTERNAL_MESSAGE_CELLS: u64 = 3;
+/// Covers storage fees and bigger payloads.
+const FEE_MARGIN_PERCENT: u64 = 20;
+
+/// Messages a single wallet is going to send.
+pub struct SenderPlan {
+    pub wallet: MsgAddressInt,
+    pub messages: u64,
+    /// Value attached to every message and not coming back, nanotons.
+    pub value: u64,
+    /// Value that must be on the wallet while messages are in flight but returns afterwards.
+    pub reserve: u64,
+    /// The first message carries the StateInit, so the wallet only has to be funded.
+    pub deploys_itself: bool,
+}
+
+pub struct FeeModel {
+    gas: GasLimitsPrices,
+    fwd: MsgForwardPrices,
+}
+
+impl FeeModel {
+    pub async fn fetch(client: &RpcClient) -> Result<Self> {
+        let config = client
+            .get_blockchain_config()
+            .await
+            .context("Failed to get blockchain config")?;
+        let config = config.raw_config();
+        Ok(Self {
+            gas: config
+                .gas_prices(false)
+                .context("No gas prices in config")?,
+            fwd: config
+                .fwd_prices(false)
+                .context("No forward prices in config")?,
+        })
+    }
+
+    /// Fees paid by the sender for one transfer: external import, compute and forwarding.
+    pub fn transfer_fee(&self) -> u64 {
+        let fee = self.gas.calc_gas_fee(TRANSFER_GAS) as u64
+            + self.forward_fee(EXTERNAL_MESSAGE_BITS, EXTERNAL_MESSAGE_CELLS)
+            + self.forward_fee(INTERNAL_MESSAGE_BITS, INTERNAL_MESSAGE_CELLS);
+        fee + fee * FEE_MARGIN_PERCENT / 100
+    }
+
+    fn forward_fee(&self, bits: u64, cells: u64) -> u64 {
+        let per_size =
+            self.fwd.bit_price as u128 * bits as u128 + self.fwd.cell_price as u128 * cells as u128;
+        self.fwd.lump_price + ((per_size + 0xffff) >> 16) as u64
+    }
+}
+
+/// Refuses to start when a sender can't pay for its share of the plan or the total estimate
+/// exceeds `--max-spend`.
+pub async fn check(client: &RpcClient, common_args: &Args, plan: &[SenderPlan]) -> Result<()> {
+    let fee = FeeModel::fetch(client).await?.transfer_fee();
+    let wallets: Vec<_> = plan.iter().map(|p| p.wallet.clone()).collect();
+    let balances = fetch_balances(client, &wallets).await?;
+    evaluate(plan, &balances, fee, common_args.max_spend)
+}
+
+/// Checks the plan against the wallet balances and deployment status.
+fn evaluate(
+    plan: &[SenderPlan],
+    balances: &[AccountBalance],
+    fee: u64,
+    max_spend: Option<u64>,
+) -> Result<()> {
+    let mut total_spend = 0u128;
+    let mut total_balance = 0u128;
+    let mut total_messages = 0u64;
+    let mut short = Vec::new();
+    for (sender, account) in plan.iter().zip(balances) {
+        let spend = sender.messages as u128 * (fee + sender.value) as u128;
+        total_spend += spend;
+        total_messages += sender.messages;
+        if !account.deployed && !sender.deploys_itself {
+            short.push(format!("{} is not deployed", sender.wallet));
+            continue;
+        }
+        total_balance += account.balance;
+        let needed = spend + sender.reserve as u128;
+        if account.balance < needed {
+            short.push(format!(
+                "{} has {}, needs {needed}",
+                sender.wallet, account.balance
+            ));
+        }
+    }
+
+    log::info!(
+        "Pre-flight: {total_messages} messages from {} wallets, fee ~{fee} per message, \
+        estimated spend {total_spend} of {total_balance} available",
+        plan.len()
+    );
+
+    if let Some(max_spend) = max_spend {
+        if total_spend > max_spend as u128 {
+            anyhow::bail!(
+                "Estimated spend {total_spend} exceeds --max-spend {max_spend}, \
+                reduce the plan or raise the limit"
+            );
+        }
+    }
+    if !short.is_empty() {
+        for line in short.iter().take(10) {
+            log::error!("Insufficient funds: {line}");
+        }
+        anyhow::bail!(
+            "{} of {} wallets can't cover the plan, top them up with `provision wallets`",
+            short.len(),
+            plan.len()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wallet(index: u32) -> MsgAddressInt {
+        format!("0:{index:064x}").parse().unwrap()
+    }
+
+    #[test]
+    fn test_transfer_fee() {
+        let fwd = MsgForwardPrices {
+            lump_price: 1_000_000,
+            bit_price: 1 << 16,
+            cell_price: 100 << 16,
+            ..Default::default()
+        };
+        let model = FeeModel {
+            gas: GasLimitsPrices::default(),
+            fwd: fwd.clone(),
+        };
+        // External 1_002_400 plus internal 1_001_800, with the 20% margin.
+        assert_eq!(model.transfer_fee(), 2_405_040);
+
+        let with_gas = FeeModel {
+            gas: GasLimitsPrices {
+                gas_price: 1000 << 16,
+                ..Default::default()
+            },
+            fwd,
+        };
+        assert!(with_gas.transfer_fee() > model.transfer_fee());
+    }
+
+    #[test]
+    fn test_max_spend_rejection() {
+        let plan: Vec<_> = (0..2)
+            .map(|i| SenderPlan {
+                wallet: wallet(i),
+                messages: 10,
+                value: 1_000,
+                reserve: 0,
+                deploys_itself: false,
+            })
+            .collect();
+        let deployed = |balance| AccountBalance {
+            balance,
+            deployed: true,
+        };
+        let balances = [deployed(1_000_000), deployed(1_000_000)];
+
+        // Two wallets sending ten messages of 1_000 plus 100 fee each.
+        assert!(evaluate(&plan, &balances, 100, None).is_ok());
+        assert!(evaluate(&plan, &balances, 100, Some(22_000)).is_ok());
+        let err = evaluate(&plan, &balances, 100, Some(21_999)).unwrap_err();
+        assert!(err.to_string().contains("--max-spend"));
+
+        assert!(evaluate(&plan, &[deployed(1_000_000), deployed(10_000)], 100, None).is_err());
+        assert!(evaluate(&plan, &[deployed(1_000_000), Default::default()], 100, None).is_err());
+    }
+
+    #[test]
+    fn test_funded_uninit_wallet() {
+        let mut plan = vec![SenderPlan {
+            wallet: wallet(0),
+            messages: 10,
+            value: 1_000,
+            reserve: 0,
+            deploys_itself: false,
+        }];
+        let funded_uninit = [AccountBalance {
+            balance: 1_000_000,
+            deployed: false,
+        }];
+        let err = evaluate(&plan, &funded_uninit, 100, None).unwrap_err();
+        assert!(err.to_string().contains("1 of 1 wallets"));
+
+        plan[0].deploys_itself = true;
+        assert!(evaluate(&plan, &funded_uninit, 100, None).is_ok());
+    }
+}
@@ -0,0 +1,555 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use ed25519_dalek::Keypair;
+use everscale_rpc_client::RpcClient;
+use futures_util::StreamExt;
+use nekoton_abi::{FunctionExt, PackAbiPlain, UnpackAbiPlain};
+use nekoton_utils::SimpleClock;
+use serde::Serialize;
+use ton_abi::{Token, TokenValue, Uint};
+use ton_block::{AccountState, AccountStuff, MsgAddressInt};
+use ton_types::{BuilderData, UInt256};
+
+use crate::abi::{
+    self, batch_ever_wallet_deploy, dudos_factory, get_wallet, BatchEverWalletDeployInput,
+    GetDeployedFunctionOutput, GetWalletFunctionInput, GetWalletFunctionOutput,
+};
+use crate::deployments::{Deployments, Role};
+use crate::report::RunStats;
+use crate::wallets::WalletKind;
+use crate::{send, Args};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Parser, Debug, Clone, Serialize)]
+pub struct ProvisionArgs {
+    #[command(subcommand)]
+    command: ProvisionCommand,
+}
+
+#[derive(Subcommand, Debug, Clone, Serialize)]
+pub enum ProvisionCommand {
+    /// Deploy and fund the WalletFactory ever-wallets used by the transfer tests
+    Wallets(ProvisionWalletsArgs),
+    /// Deploy a range of dudos-factory receivers used by `dag`
+    Receivers(ProvisionReceiversArgs),
+}
+
+#[derive(Parser, Debug, Clone, Serialize)]
+pub struct ProvisionWalletsArgs {
+    #[clap(short, long)]
+    /// Number of wallets, indices `0..total_wallets`
+    total_wallets: u32,
+
+    #[clap(long, default_value = "50")]
+    /// Wallets deployed by a single `batchEverWalletDeploy` call
+    batch_size: u32,
+
+    #[clap(long, default_value = "1000000000")]
+    /// Balance in nanotons every wallet must have
+    target_balance: u64,
+
+    #[clap(long, default_value = "100000000")]
+    /// Extra nanotons per wallet attached to deploy calls to pay for the deployment
+    deploy_fee: u64,
+
+    #[clap(long, default_value = "10")]
+    /// Top-up transfers sent concurrently
+    max_concurrent: usize,
+
+    #[clap(long, default_value = "300")]
+    /// Seconds to wait for deployments and top-ups to land
+    timeout: u64,
+}
+
+#[derive(Parser, Debug, Clone, Serialize)]
+pub struct ProvisionReceiversArgs {
+    #[clap(short, long)]
+    /// Number of receivers to deploy
+    count: u32,
+
+    #[clap(long, default_value = "0")]
+    /// First receiver nonce, give every operator its own range to provision in parallel
+    offset: u32,
+
+    #[clap(long, default_value = "100")]
+    /// Receivers deployed by a single `deploy_internal` call
+    batch_size: u32,
+
+    #[clap(long, default_value = "100000000")]
+    /// Balance in nanotons of every receiver
+    balance: u64,
+
+    #[clap(long, default_value = "100000000")]
+    /// Extra nanotons per receiver attached to deploy calls to pay for the deployment
+    deploy_fee: u64,
+
+    #[clap(long, default_value = "120")]
+    /// Seconds to wait for a batch to be deployed
+    timeout: u64,
+}
+
+pub(crate) async fn run(
+    args: ProvisionArgs,
+    common_args: Args,
+    keypair: Arc<Keypair>,
+    client: RpcClient,
+    stats: RunStats,
+) -> Result<()> {
+    match args.command {
+        ProvisionCommand::Wallets(args) => {
+            provision_wallets(args, common_args, keypair, client, stats).await
+        }
+        ProvisionCommand::Receivers(args) => {
+            provision_receivers(args, common_args, keypair, client, stats).await
+        }
+    }
+}
+
+async fn provision_wallets(
+    args: ProvisionWalletsArgs,
+    common_args: Args,
+    keypair: Arc<Keypair>,
+    client: RpcClient,
+    stats: RunStats,
+) -> Result<()> {
+    if args.batch_size == 0 {
+        anyhow::bail!("Batch size must be positive");
+    }
+
+    let deployments = Deployments::load(&common_args)?;
+    let factory = deployments.one(Role::WalletFactory)?.address;
+    let master = deployments.one(Role::SenderWallet)?.address;
+    log::info!("Factory: {factory}, master wallet: {master}");
+
+    let wallets = get_wallets(&client, &factory, args.total_wallets, &keypair).await?;
+    let balances = fetch_balances(&client, &wallets).await?;
+
+    let missing: Vec<u32> = (0..args.total_wallets)
+        .filter(|&i| !balances[i as usize].deployed)
+        .collect();
+    log::info!(
+        "{} of {} wallets are already deployed",
+        args.total_wallets as usize - missing.len(),
+        args.total_wallets
+    );
+
+    let timeout = Duration::from_secs(args.timeout);
+    if !missing.is_empty() {
+        let deployed_before = get_deployed(&client, &factory).await?;
+        let batches = split_into_batches(&missing, args.batch_size);
+        for (start, count) in &batches {
+            let value = (args.target_balance + args.deploy_fee) * *count as u64;
+            let payload = batch_deploy_payload(*start, *count, args.target_balance, &keypair)?;
+            log::info!("Deploying wallets {}..{}", start, start + count);
+
+            let master_state = get_state(&client, &master).await?;
+            stats.record_built();
+            let started = Instant::now();
+            let result = send::send(
+                &client,
+                &keypair,
+                WalletKind::EverWallet,
+                master.clone(),
+                payload,
+                factory.clone(),
+                value,
+                &master_state,
+            )
+            .await;
+            stats.record_send(started.elapsed(), &result);
+            result.with_context(|| format!("Failed to deploy wallets from {start}"))?;
+        }
+
+        let expected = deployed_before + missing.len() as u64;
+        let deadline = Instant::now() + timeout;
+        loop {
+            let deployed = get_deployed(&client, &factory).await?;
+            if deployed >= expected {
+                log::info!("Factory reports {deployed} deployed wallets");
+                break;
+            }
+            if Instant::now() > deadline {
+                log::warn!("Factory reports {deployed} deployed wallets, expected {expected}");
+                break;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    let balances = fetch_balances(&client, &wallets).await?;
+    let top_ups: Vec<_> = balances
+        .iter()
+        .enumerate()
+        .filter(|(_, account)| account.deployed && account.balance < args.target_balance as u128)
+        .map(|(i, account)| (i, args.target_balance - account.balance as u64))
+        .collect();
+
+    if !top_ups.is_empty() {
+        log::info!("Topping up {} wallets", top_ups.len());
+        let master_state = get_state(&client, &master).await?;
+        futures_util::stream::iter(top_ups)
+            .for_each_concurrent(args.max_concurrent, |(i, amount)| {
+                let (client, keypair, master, state, stats) =
+                    (&client, &keypair, &master, &master_state, &stats);
+                let wallet = wallets[i].clone();
+                async move {
+                    stats.record_built();
+                    let started = Instant::now();
+                    let result = send::send(
+                        client,
+                        keypair,
+                        WalletKind::EverWallet,
+                        master.clone(),
+                        BuilderData::new(),
+                        wallet,
+                        amount,
+                        state,
+                    )
+                    .await;
+                    stats.record_send(started.elapsed(), &result);
+                    if let Err(e) = result {
+                        log::error!("Failed to top up wallet {i}: {e:?}");
+                    }
+                }
+            })
+            .await;
+    }
+
+    // Deployments and top-ups land asynchronously, poll until every wallet is ready.
+    let target = args.target_balance as u128;
+    let deadline = Instant::now() + timeout;
+    let not_ready = loop {
+        let balances = fetch_balances(&client, &wallets).await?;
+        let not_ready: Vec<_> = balances
+            .iter()
+            .enumerate()
+            .filter(|(_, account)| !account.deployed || account.balance < target)
+            .map(|(i, account)| (i as u32, *account))
+            .collect();
+        if not_ready.is_empty() || Instant::now() > deadline {
+            break not_ready;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    };
+
+    let ready: Vec<u32> = (0..args.total_wallets)
+        .filter(|i| !not_ready.iter().any(|(j, _)| j == i))
+        .collect();
+    stats.add_confirmed(ready.len() as u64);
+
+    log::info!(
+        "Ready wallets ({}/{}): {}",
+        ready.len(),
+        args.total_wallets,
+        format_ranges(&ready)
+    );
+    for (i, account) in &not_ready {
+        if account.deployed {
+            log::warn!(
+                "Wallet {i} ({}) has {} of {} nanotons",
+                wallets[*i as usize],
+                account.balance,
+                args.target_balance
+            );
+        } else {
+            log::warn!(
+                "Wallet {i} ({}) is not deployed, holds {} nanotons",
+                wallets[*i as usize],
+                account.balance
+            );
+        }
+    }
+
+    if !not_ready.is_empty() {
+        anyhow::bail!("{} wallets are not ready", not_ready.len());
+    }
+    Ok(())
+}
+
+async fn provision_receivers(
+    args: ProvisionReceiversArgs,
+    common_args: Args,
+    keypair: Arc<Keypair>,
+    client: RpcClient,
+    stats: RunStats,
+) -> Result<()> {
+    if args.batch_size == 0 {
+        anyhow::bail!("Batch size must be positive");
+    }
+    let end = args
+        .offset
+        .checked_add(args.count)
+        .context("Receiver range overflows")?;
+
+    let deployments = Deployments::load(&common_args)?;
+    let factory = deployments.one(Role::DudosFactory)?.address;
+    let master = deployments.one(Role::SenderWallet)?.address;
+    log::info!(
+        "Factory: {factory}, master wallet: {master}, nonces {}..{end}",
+        args.offset
+    );
+
+    let receivers = get_receivers(&client, &factory, args.offset, args.count).await?;
+    let balances = fetch_balances(&client, &receivers).await?;
+    let missing = missing_receivers(args.offset, &balances);
+    match missing.first() {
+        None => {
+            log::info!("All {} receivers are already deployed", args.count);
+            return Ok(());
+        }
+        Some(first) if *first > args.offset => log::info!("Resuming from nonce {first}"),
+        Some(_) => {}
+    }
+
+    let timeout = Duration::from_secs(args.timeout);
+    for (start, count) in split_into_batches(&missing, args.batch_size) {
+        log::info!("Deploying receivers {}..{}", start, start + count);
+        let value = (args.balance + args.deploy_fee) * count as u64;
+        let tokens = [
+            Token::new("count", TokenValue::Uint(Uint::new(count as u128, 32))),
+            Token::new("offset", TokenValue::Uint(Uint::new(start as u128, 256))),
+            Token::new(
+                "balance",
+                TokenValue::Uint(Uint::new(args.balance as u128, 128)),
+            ),
+        ];
+        let payload = dudos_factory()
+            .function("deploy_internal")?
+            .encode_internal_input(&tokens)?;
+
+        let master_state = get_state(&client, &master).await?;
+        stats.record_built();
+        let started = Instant::now();
+        let result = send::send(
+            &client,
+            &keypair,
+            WalletKind::EverWallet,
+            master.clone(),
+            payload,
+            factory.clone(),
+            value,
+            &master_state,
+        )
+        .await;
+        stats.record_send(started.elapsed(), &result);
+        result.with_context(|| format!("Failed to deploy receivers from {start}"))?;
+
+        // Wait for the whole batch, so an interrupted run resumes from the right nonce.
+        let batch = &receivers[(start - args.offset) as usize..][..count as usize];
+        let deadline = Instant::now() + timeout;
+        loop {
+            let balances = fetch_balances(&client, batch).await?;
+            let deployed = balances.iter().filter(|b| b.deployed).count();
+            if deployed == batch.len() {
+                stats.add_confirmed(deployed as u64);
+                break;
+            }
+            if Instant::now() > deadline {
+                let pending = missing_receivers(start, &balances);
+                anyhow::bail!(
+                    "Receivers {} are not deployed after {:?}, rerun to resume",
+                    format_ranges(&pending),
+                    timeout
+                );
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    log::info!(
+        "Deployed receivers {}, nonces {}..{end} are ready",
+        format_ranges(&missing),
+        args.offset
+    );
+    Ok(())
+}
+
+/// Nonces of the receivers that are not active yet, counting from `offset`. Funds sent before
+/// the deployment leave an uninitialized account behind, it still needs the deployment.
+fn missing_receivers(offset: u32, balances: &[AccountBalance]) -> Vec<u32> {
+    (offset..)
+        .zip(balances)
+        .filter(|(_, account)| !account.deployed)
+        .map(|(nonce, _)| nonce)
+        .collect()
+}
+
+/// Groups sorted indices into `(start, count)` runs of consecutive indices, at most `batch_size` long.
+fn split_into_batches(indices: &[u32], batch_size: u32) -> Vec<(u32, u32)> {
+    let mut batches: Vec<(u32, u32)> = Vec::new();
+    for &index in indices {
+        match batches.last_mut() {
+            Some((start, count)) if *start + *count == index && *count < batch_size => *count += 1,
+            _ => batches.push((index, 1)),
+        }
+    }
+    batches
+}
+
+fn format_ranges(indices: &[u32]) -> String {
+    if indices.is_empty() {
+        return "none".to_string();
+    }
+    split_into_batches(indices, u32::MAX)
+        .into_iter()
+        .map(|(start, count)| match count {
+            1 => start.to_string(),
+            _ => format!("{}-{}", start, start + count - 1),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn batch_deploy_payload(
+    start: u32,
+    count: u32,
+    balance: u64,
+    keypair: &Keypair,
+) -> Result<BuilderData> {
+    let tokens = BatchEverWalletDeployInput {
+        index: start as _,
+        balance: balance as _,
+        public_key: UInt256::from(keypair.public.to_bytes()),
+        count: count as _,
+    }
+    .pack();
+    batch_ever_wallet_deploy().encode_internal_input(&tokens)
+}
+
+async fn get_state(client: &RpcClient, address: &MsgAddressInt) -> Result<AccountStuff> {
+    Ok(client
+        .get_contract_state(address, None)
+        .await?
+        .with_context(|| format!("No state for {address}"))?
+        .account)
+}
+
+async fn get_deployed(client: &RpcClient, factory: &MsgAddressInt) -> Result<u64> {
+    let state = get_state(client, factory).await?;
+    let result = abi::get_deployed().run_local(&SimpleClock, state, &[])?;
+    let output: GetDeployedFunctionOutput = result.tokens.context("No tokens")?.unpack()?;
+    Ok(output.deployed)
+}
+
+pub(crate) async fn get_wallets(
+    client: &RpcClient,
+    factory: &MsgAddressInt,
+    num_wallets: u32,
+    keypair: &Keypair,
+) -> Result<Vec<MsgAddressInt>> {
+    let method = get_wallet();
+    let state = get_state(client, factory).await?;
+
+    let mut wallets = Vec::with_capacity(num_wallets as usize);
+    for i in 0..num_wallets {
+        let tokens = GetWalletFunctionInput {
+            index: i as _,
+            public_key: UInt256::from(keypair.public.to_bytes()),
+        }
+        .pack();
+        let result = method.run_local(&SimpleClock, state.clone(), &tokens)?;
+        let output: GetWalletFunctionOutput = result.tokens.context("No tokens")?.unpack()?;
+        wallets.push(output.receiver);
+    }
+    Ok(wallets)
+}
+
+async fn get_receivers(
+    client: &RpcClient,
+    factory: &MsgAddressInt,
+    offset: u32,
+    count: u32,
+) -> Result<Vec<MsgAddressInt>> {
+    #[derive(UnpackAbiPlain)]
+    struct Output {
+        #[abi(address)]
+        receiver: MsgAddressInt,
+    }
+
+    let method = dudos_factory().function("get_receiver")?;
+    let state = get_state(client, factory).await?;
+
+    let mut receivers = Vec::with_capacity(count as usize);
+    for nonce in offset..offset + count {
+        let tokens = [Token::new(
+            "nonce",
+            TokenValue::Uint(Uint::new(nonce as u128, 256)),
+        )];
+        let result = method.run_local(&SimpleClock, state.clone(), &tokens)?;
+        let output: Output = result.tokens.context("No tokens")?.unpack()?;
+        receivers.push(output.receiver);
+    }
+    Ok(receivers)
+}
+
+/// Balance of an account and whether its code is deployed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct AccountBalance {
+    /// Nanotons, zero for accounts that don't exist.
+    pub balance: u128,
+    /// The account is active. Uninitialized and frozen accounts can hold a balance too.
+    pub deployed: bool,
+}
+
+impl AccountBalance {
+    fn of(account: &AccountStuff) -> Self {
+        Self {
+            balance: account.storage.balance.grams.as_u128(),
+            deployed: matches!(account.storage.state, AccountState::AccountActive { .. }),
+        }
+    }
+}
+
+/// Balances and deployment status of the wallets, in the same order.
+pub(crate) async fn fetch_balances(
+    client: &RpcClient,
+    wallets: &[MsgAddressInt],
+) -> Result<Vec<AccountBalance>> {
+    futures_util::stream::iter(wallets)
+        .map(|wallet| async move {
+            let state = client.get_contract_state(wallet, None).await?;
+            Ok(state
+                .map(|s| AccountBalance::of(&s.account))
+                .unwrap_or_default())
+        })
+        .buffered(100)
+        .collect::<Vec<Result<_>>>()
+        .await
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_into_batches() {
+        assert_eq!(
+            split_into_batches(&[0, 1, 2, 3, 4, 7, 8, 10], 3),
+            vec![(0, 3), (3, 2), (7, 2), (10, 1)]
+        );
+        assert!(split_into_batches(&[], 3).is_empty());
+        assert_eq!(format_ranges(&[0, 1, 2, 5, 7, 8]), "0-2, 5, 7-8");
+    }
+
+    #[test]
+    fn test_funded_uninit_receiver_is_missing() {
+        let active = AccountBalance {
+            balance: 1_000,
+            deployed: true,
+        };
+        // Funds sent to the address before the deployment.
+        let funded_uninit = AccountBalance {
+            balance: 1_000,
+            deployed: false,
+        };
+        let balances = [active, funded_uninit, active, AccountBalance::default()];
+        assert_eq!(missing_receivers(10, &balances), vec![11, 13]);
+        assert!(missing_receivers(10, &[active, active]).is_empty());
+    }
+}
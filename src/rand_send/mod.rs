@@ -1,29 +1,32 @@
-use crate::abi::{get_wallet, GetWalletFunctionInput, GetWalletFunctionOutput};
-use crate::models::GenericDeploymentInfo;
-use crate::send::send;
+use crate::endpoints;
+use crate::preflight::{self, SenderPlan};
+use crate::report::RunStats;
+use crate::send::{send_batch, Transfer};
+use crate::wallets::{self, Sender, WalletKind};
 use crate::Args;
-use anyhow::{Context, Result};
+use anyhow::Result;
 use clap::Parser;
 use ed25519_dalek::Keypair;
 use everscale_rpc_client::RpcClient;
 use futures_util::StreamExt;
-use nekoton_abi::{FunctionExt, PackAbiPlain, UnpackAbiPlain};
-use nekoton_utils::SimpleClock;
 use rand::prelude::{SliceRandom, StdRng};
 use rand::SeedableRng;
+use serde::Serialize;
 use std::collections::HashMap;
 use std::io::Write;
 use std::path::Path;
 use std::sync::atomic::AtomicU64;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::task::JoinHandle;
 use ton_block::{AccountStuff, MsgAddressInt};
-use ton_types::{BuilderData, UInt256};
 
 mod rate_limiter;
 
-#[derive(Parser, Debug, Clone)]
+/// Value of every transfer between the test wallets.
+const TRANSFER_AMOUNT: u64 = 100_000_000;
+
+#[derive(Parser, Debug, Clone, Serialize)]
 pub struct RandSendTestArgs {
     #[clap(short, long)]
     total_wallets: u32,
@@ -41,6 +44,10 @@ pub struct RandSendTestArgs {
     
     #[clap(long)]
     save_accounts: Option<std::path::PathBuf>,
+
+    #[clap(long, default_value = "1")]
+    /// Transfers packed into every external message, each to its own random destination
+    gifts_per_message: u32,
 }
 
 pub async fn run(
@@ -48,41 +55,19 @@ pub async fn run(
     common_args: Args,
     key_pair: Arc<Keypair>,
     client: RpcClient,
+    stats: RunStats,
 ) -> Result<()> {
-    let deployments_path = common_args.project_root.join("deployments");
-    log::info!("Deployments path: {:?}", deployments_path);
-
-    let factory_abi = walkdir::WalkDir::new(&deployments_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| e.path().extension().map(|e| e == "json").unwrap_or(false))
-        .find(|e| {
-            e.path()
-                .file_name()
-                .and_then(|n| n.to_str())
-                .map(|n| n.to_lowercase().contains("factory"))
-                .unwrap_or(false)
-        })
-        .context("No factory abi")?;
-    let factory: GenericDeploymentInfo =
-        serde_json::from_slice(&std::fs::read(factory_abi.path())?)?;
-    let mut recievers = get_wallets(
-        client.clone(),
-        &factory.address,
-        swap_args.total_wallets,
-        key_pair.public.to_bytes(),
-    )
-    .await
-    .context("Failed to get wallets")?;
-    recievers.sort();
+    let mut wallets =
+        wallets::load_senders(&common_args, &client, &key_pair, swap_args.total_wallets).await?;
+    wallets.sort_by(|a, b| a.address.cmp(&b.address));
+    wallets::check_gifts_per_message(&wallets, swap_args.gifts_per_message)?;
 
     if let Some(path) = &swap_args.save_accounts {
-        save_accounts_to_file(&recievers, path)?;
-        log::info!("Saved {} accounts to {:?}", recievers.len(), path);
+        save_accounts_to_file(&wallets, path)?;
+        log::info!("Saved {} accounts to {:?}", wallets.len(), path);
     }
 
-    spawn_ddos_jobs(&swap_args, client, recievers, common_args, key_pair).await?;
+    spawn_ddos_jobs(&swap_args, client, wallets, common_args, stats).await?;
 
     Ok(())
 }
@@ -90,25 +75,43 @@ pub async fn run(
 async fn spawn_ddos_jobs(
     args: &RandSendTestArgs,
     client: RpcClient,
-    receivers: Vec<MsgAddressInt>,
+    wallets: Vec<Sender>,
     common_args: Args,
-    key_pair: Arc<Keypair>,
+    stats: RunStats,
 ) -> Result<()> {
     let mut params =
         rate_limiter::LoadPattern::new(args.from_rps as f64, args.to_rps as f64, args.rps as f64)?;
     params.calibrate(args.num_seconds);
     let tps = params.generate_timeline(args.num_seconds);
 
+    // Senders are picked at random, plan for an even share of the timeline.
+    let total: u64 = tps.iter().sum();
+    let per_wallet =
+        total.div_ceil(wallets.len().max(1) as u64) * args.gifts_per_message as u64;
+    let plan: Vec<_> = wallets
+        .iter()
+        .map(|wallet| SenderPlan {
+            wallet: wallet.address.clone(),
+            messages: per_wallet,
+            value: TRANSFER_AMOUNT,
+            reserve: 0,
+            deploys_itself: wallet.kind == WalletKind::EverWallet,
+        })
+        .collect();
+    preflight::check(&client, &common_args, &plan).await?;
+
     let mut rng = StdRng::seed_from_u64(common_args.seed.unwrap_or_default());
-    let counter = Arc::new(AtomicU64::new(0));
 
     let states: HashMap<_, _> = {
         let client = client.clone();
-        futures_util::stream::iter(receivers.iter())
+        futures_util::stream::iter(wallets.iter().map(|wallet| &wallet.address))
             .map(move |addr| {
                 let client = client.clone();
                 async move {
-                    let state = match client.get_contract_state(addr, None).await {
+                    let state = endpoints::call(&client, |client| async move {
+                        client.get_contract_state(addr, None).await
+                    });
+                    let state = match state.await {
                         Ok(Some(state)) => Arc::new(state.account),
                         _ => panic!("Failed to get state for {addr}"),
                     };
@@ -121,27 +124,29 @@ async fn spawn_ddos_jobs(
             .await
     };
 
-    spawn_progress_printer(counter.clone());
+    let progress_printer = spawn_progress_printer(stats.sent.clone());
     const WINDOW_LEN: u64 = 1;
     let mut interval = tokio::time::interval(Duration::from_secs(WINDOW_LEN));
 
     for tps in tps {
+        stats.set_target_rps(tps);
         let barrier = Arc::new(tokio::sync::Barrier::new(tps as usize + 1));
         let start = std::time::Instant::now();
         let tps = tps * WINDOW_LEN;
         for _ in 0..tps {
             let barrier = barrier.clone();
             let client = client.clone();
-            let from = receivers.choose(&mut rng).unwrap().clone();
-            let to = receivers.choose(&mut rng).unwrap().clone();
-            let signer = key_pair.clone();
-            let counter = counter.clone();
-            let from_state = states.get(&from).unwrap().clone();
+            let from = wallets.choose(&mut rng).unwrap().clone();
+            let to: Vec<_> = (0..args.gifts_per_message)
+                .map(|_| wallets.choose(&mut rng).unwrap().address.clone())
+                .collect();
+            let stats = stats.clone();
+            let from_state = states.get(&from.address).unwrap().clone();
 
             tokio::spawn(async move {
-                ddos_job(client, from, to, signer, counter, from_state)
-                    .await
-                    .unwrap();
+                if let Err(e) = ddos_job(client, from, to, stats, from_state).await {
+                    log::error!("Failed to send: {:?}", e);
+                }
                 if !common_args.no_wait {
                     barrier.wait().await;
                 }
@@ -162,30 +167,43 @@ async fn spawn_ddos_jobs(
         }
         interval.tick().await;
     }
+    progress_printer.abort();
 
     Ok(())
 }
 
 async fn ddos_job(
     rpc_client: RpcClient,
-    from: MsgAddressInt,
-    to: MsgAddressInt,
-    signer: Arc<Keypair>,
-    counter: Arc<AtomicU64>,
+    from: Sender,
+    to: Vec<MsgAddressInt>,
+    stats: RunStats,
     from_state: Arc<AccountStuff>,
 ) -> Result<()> {
-    send(
+    let count = to.len() as u64;
+    let mut transfers = Vec::with_capacity(to.len());
+    for destination in to {
+        transfers.push(Transfer {
+            destination,
+            amount: TRANSFER_AMOUNT,
+            payload: from.transfer_payload()?,
+        });
+    }
+    stats.record_built();
+    let start = Instant::now();
+    let result = send_batch(
         &rpc_client,
-        &signer,
-        from,
-        BuilderData::new(),
-        to,
-        100_000_000,
+        &from.keypair,
+        from.kind,
+        from.address,
+        transfers,
         &from_state,
     )
-    .await?;
-    counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    Ok(())
+    .await;
+    stats.record_send(start.elapsed(), &result);
+    if result.is_ok() {
+        stats.record_transfers(count);
+    }
+    result
 }
 
 pub fn spawn_progress_printer(counter: Arc<AtomicU64>) -> JoinHandle<()> {
@@ -201,39 +219,10 @@ pub fn spawn_progress_printer(counter: Arc<AtomicU64>) -> JoinHandle<()> {
         }
     })
 }
-fn save_accounts_to_file(accounts: &[MsgAddressInt], path: &Path) -> Result<()> {
+fn save_accounts_to_file(accounts: &[Sender], path: &Path) -> Result<()> {
     let mut file = std::fs::File::create(path)?;
     for account in accounts {
-        writeln!(file, "{account}")?;
+        writeln!(file, "{}", account.address)?;
     }
     Ok(())
 }
-
-async fn get_wallets(
-    client: RpcClient,
-    factory: &MsgAddressInt,
-    num_wallets: u32,
-    pubkey: [u8; 32],
-) -> Result<Vec<MsgAddressInt>> {
-    let method = get_wallet();
-    let state = client
-        .get_contract_state(factory, None)
-        .await?
-        .context("No state")?;
-
-    let mut recipients = Vec::new();
-    for i in 0..num_wallets {
-        let tokens = GetWalletFunctionInput {
-            index: i as _,
-            public_key: UInt256::from(pubkey),
-        }
-        .pack();
-        let result = method.run_local(&SimpleClock, state.account.clone(), &tokens)?;
-        let tokens = result.tokens.context("No tokens")?;
-        let addr: GetWalletFunctionOutput = tokens.unpack()?;
-        recipients.push(addr.receiver);
-    }
-
-    Ok(recipients)
-}
-
@@ -0,0 +1,119 @@
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use everscale_rpc_client::RpcClient;
+use governor::RateLimiter;
+use serde::Serialize;
+
+use crate::archive::ArchiveReader;
+use crate::endpoints;
+use crate::rand_send::spawn_progress_printer;
+use crate::report::RunStats;
+use crate::Args;
+
+#[derive(Parser, Debug, Clone, Serialize)]
+pub struct ReplayArgs {
+    #[clap(short, long)]
+    /// Archive produced with `--record`
+    archive: PathBuf,
+
+    #[clap(short, long)]
+    /// Broadcast at a fixed rate instead of the recorded timing
+    rps: Option<u32>,
+
+    #[clap(long, default_value = "1.0")]
+    /// Speed multiplier for the recorded timing
+    speed: f64,
+
+    #[clap(long, default_value = "100")]
+    /// Maximum concurrent broadcasts
+    max_concurrent: NonZeroUsize,
+}
+
+pub(crate) async fn run(
+    replay_args: ReplayArgs,
+    _common_args: Args,
+    client: RpcClient,
+    stats: RunStats,
+) -> Result<()> {
+    if replay_args.speed <= 0.0 {
+        anyhow::bail!("Speed must be positive");
+    }
+
+    let file = std::fs::File::open(&replay_args.archive)
+        .with_context(|| format!("Failed to open archive {:?}", replay_args.archive))?;
+    let archive = ArchiveReader::new(std::io::BufReader::new(file))?;
+
+    let rate_limiter = replay_args
+        .rps
+        .map(|rps| {
+            NonZeroU32::new(rps)
+                .map(|rps| RateLimiter::direct(governor::Quota::per_second(rps)))
+                .context("rps must be positive")
+        })
+        .transpose()?;
+
+    match replay_args.rps {
+        Some(rps) => log::info!("Replaying {:?} at {} rps", replay_args.archive, rps),
+        None => log::info!(
+            "Replaying {:?} with recorded timing at {}x speed",
+            replay_args.archive,
+            replay_args.speed
+        ),
+    }
+
+    stats.set_target_rps(replay_args.rps.unwrap_or_default() as u64);
+
+    let semaphore = Arc::new(tokio::sync::Semaphore::new(
+        replay_args.max_concurrent.get(),
+    ));
+    let progress_printer = spawn_progress_printer(stats.sent.clone());
+    let start = tokio::time::Instant::now();
+    let mut total = 0u64;
+
+    for frame in archive {
+        let (offset, message) = frame?;
+        match &rate_limiter {
+            Some(rl) => rl.until_ready().await,
+            None => {
+                tokio::time::sleep_until(start + offset.div_f64(replay_args.speed)).await;
+            }
+        }
+
+        let permit = semaphore.clone().acquire_owned().await?;
+        let client = client.clone();
+        let stats = stats.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            stats.record_built();
+            let started = Instant::now();
+            let result = endpoints::call(&client, |client| async move {
+                client.broadcast_message(message).await
+            })
+            .await;
+            stats.record_result(started.elapsed(), &result);
+            if let Err(e) = result {
+                log::error!("Failed to send: {:?}", e);
+            }
+        });
+        total += 1;
+    }
+
+    // Wait for in-flight broadcasts.
+    let _ = semaphore
+        .acquire_many(replay_args.max_concurrent.get() as u32)
+        .await?;
+    progress_printer.abort();
+
+    log::info!(
+        "Replayed {} messages in {:?}",
+        total,
+        Duration::from_secs(start.elapsed().as_secs())
+    );
+
+    Ok(())
+}
@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use ed25519_dalek::Keypair;
+use everscale_rpc_client::RpcClient;
+use serde::{Deserialize, Serialize};
+
+use crate::report::{PhaseReport, RunReport, RunStats};
+use crate::{Args, Commands};
+
+#[derive(Parser, Debug, Clone, Serialize)]
+pub struct ScenarioArgs {
+    #[clap(short, long)]
+    /// Path to the scenario file (.toml, .yaml or .yml)
+    file: PathBuf,
+
+    #[clap(long, default_value = "false")]
+    /// Keep running the next phases when a phase fails
+    continue_on_error: bool,
+}
+
+/// Scenario file layout.
+///
+/// Phases run one after another, every `run` entry of a phase runs concurrently.
+/// A `run` entry is a subcommand name plus its arguments, spelled as on the command line:
+///
+/// ```toml
+/// [[phase]]
+/// name = "warm-up"
+/// [[phase.run]]
+/// command = "send"
+/// total-wallets = 10
+/// rps = 50
+/// num-iterations = 600
+///
+/// [[phase]]
+/// name = "swap-with-probe"
+/// [[phase.run]]
+/// command = "swap"
+/// num-swaps = 100
+/// rps = 20
+/// depth = 4
+/// [[phase.run]]
+/// command = "latency"
+/// num-txs = 500
+/// rps = 5
+/// ```
+#[derive(Debug, Deserialize)]
+struct ScenarioFile {
+    #[serde(rename = "phase", alias = "phases")]
+    phases: Vec<Phase>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Phase {
+    name: String,
+    run: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Step {
+    command: String,
+    #[serde(flatten)]
+    args: BTreeMap<String, serde_json::Value>,
+}
+
+impl Step {
+    /// Turns the step into command line arguments, so clap applies the same defaults and
+    /// validation as for a regular invocation.
+    fn to_argv(&self) -> Result<Vec<String>> {
+        let mut argv = vec![self.command.clone()];
+        for (key, value) in &self.args {
+            let flag = format!("--{}", key.replace('_', "-"));
+            match value {
+                serde_json::Value::Bool(true) => argv.push(flag),
+                serde_json::Value::Bool(false) | serde_json::Value::Null => {}
+                serde_json::Value::Array(values) => {
+                    for value in values {
+                        argv.push(flag.clone());
+                        argv.push(scalar_to_string(key, value)?);
+                    }
+                }
+                value => {
+                    argv.push(flag);
+                    argv.push(scalar_to_string(key, value)?);
+                }
+            }
+        }
+        Ok(argv)
+    }
+
+    fn parse(&self) -> Result<Commands> {
+        #[derive(Parser, Debug)]
+        #[command(no_binary_name = true)]
+        struct StepCommand {
+            #[command(subcommand)]
+            command: Commands,
+        }
+
+        let argv = self.to_argv()?;
+        let parsed = StepCommand::try_parse_from(&argv)
+            .with_context(|| format!("Invalid arguments for `{}`", argv.join(" ")))?;
+        if let Commands::Scenario(_) = parsed.command {
+            anyhow::bail!("Nested scenarios are not supported");
+        }
+
+        Ok(parsed.command)
+    }
+}
+
+fn scalar_to_string(key: &str, value: &serde_json::Value) -> Result<String> {
+    match value {
+        serde_json::Value::String(s) => Ok(s.clone()),
+        serde_json::Value::Number(n) => Ok(n.to_string()),
+        serde_json::Value::Bool(b) => Ok(b.to_string()),
+        _ => anyhow::bail!("Unsupported value for `{key}`: {value}"),
+    }
+}
+
+fn load(path: &Path) -> Result<ScenarioFile> {
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read scenario file {path:?}"))?;
+    let scenario: ScenarioFile = match path.extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&data).context("Failed to parse TOML scenario")?,
+        Some("yaml" | "yml") => {
+            serde_yaml::from_str(&data).context("Failed to parse YAML scenario")?
+        }
+        _ => anyhow::bail!("Unknown scenario format {path:?}, expected .toml, .yaml or .yml"),
+    };
+
+    if scenario.phases.is_empty() {
+        anyhow::bail!("Scenario has no phases");
+    }
+    for phase in &scenario.phases {
+        if phase.run.is_empty() {
+            anyhow::bail!("Phase `{}` has nothing to run", phase.name);
+        }
+    }
+
+    Ok(scenario)
+}
+
+pub(crate) async fn run(
+    scenario_args: ScenarioArgs,
+    common_args: Args,
+    keypair: Arc<Keypair>,
+    client: RpcClient,
+    report: &mut RunReport,
+) -> Result<()> {
+    let scenario = load(&scenario_args.file)?;
+
+    // Parse everything upfront so a typo in the last phase doesn't surface after an hour of load.
+    let mut phases = Vec::with_capacity(scenario.phases.len());
+    for phase in &scenario.phases {
+        let commands = phase
+            .run
+            .iter()
+            .map(|step| step.parse())
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("Invalid phase `{}`", phase.name))?;
+        phases.push((phase.name.clone(), commands));
+    }
+
+    log::info!(
+        "Loaded scenario {:?} with {} phases",
+        scenario_args.file,
+        phases.len()
+    );
+
+    for (phase_idx, (phase_name, commands)) in phases.into_iter().enumerate() {
+        log::info!(
+            "=== Phase {}: {} ({} concurrent) ===",
+            phase_idx + 1,
+            phase_name,
+            commands.len()
+        );
+
+        let jobs = commands.into_iter().map(|command| {
+            let mut phase_args = common_args.clone();
+            phase_args.command = command.clone();
+            let keypair = keypair.clone();
+            let client = client.clone();
+            let phase_name = phase_name.clone();
+            async move {
+                let stats = RunStats::new();
+                let mut report = RunReport::new(&phase_args);
+                let result =
+                    crate::run_command(command, phase_args, keypair, client, stats.clone()).await;
+                if let Err(e) = &result {
+                    log::error!(
+                        "Phase `{phase_name}` command `{}` failed: {e:?}",
+                        report.command
+                    );
+                }
+                report.finish(&stats, &result);
+                PhaseReport {
+                    phase: phase_name,
+                    report,
+                }
+            }
+        });
+        let phase_reports = futures_util::future::join_all(jobs).await;
+
+        let failed = phase_reports.iter().any(|x| x.report.error.is_some());
+        report.phases.extend(phase_reports);
+
+        if failed && !scenario_args.continue_on_error {
+            log::error!("Phase `{phase_name}` failed, skipping the remaining phases");
+            break;
+        }
+    }
+
+    print_summary(&report.phases);
+
+    let num_failed = report
+        .phases
+        .iter()
+        .filter(|x| x.report.error.is_some())
+        .count();
+    if num_failed > 0 {
+        anyhow::bail!("{num_failed} scenario step(s) failed");
+    }
+
+    Ok(())
+}
+
+fn print_summary(phases: &[PhaseReport]) {
+    log::info!("\n=== Scenario Summary ===");
+    for phase in phases {
+        let report = &phase.report;
+        let status = match &report.error {
+            None => "ok".to_string(),
+            Some(e) => format!("failed: {e}"),
+        };
+        log::info!(
+            "{:<24} {:<16} {:>10} sent: {:<8} confirmed: {:<8} failed: {:<8} {}",
+            phase.phase,
+            report.command,
+            humantime::format_duration(Duration::from_secs(report.duration_secs as u64))
+                .to_string(),
+            report.counts.sent,
+            report.counts.confirmed,
+            report.counts.failed,
+            status
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toml_and_yaml_are_equivalent() {
+        let toml = r#"
+            [[phase]]
+            name = "warm-up"
+            [[phase.run]]
+            command = "send"
+            total_wallets = 10
+            rps = 50
+            num-iterations = 600
+            only-stats = true
+        "#;
+        let yaml = r#"
+phases:
+  - name: warm-up
+    run:
+      - command: send
+        total_wallets: 10
+        rps: 50
+        num-iterations: 600
+        only-stats: true
+"#;
+        let toml: ScenarioFile = toml::from_str(toml).unwrap();
+        let yaml: ScenarioFile = serde_yaml::from_str(yaml).unwrap();
+
+        let toml_argv = toml.phases[0].run[0].to_argv().unwrap();
+        let yaml_argv = yaml.phases[0].run[0].to_argv().unwrap();
+        assert_eq!(toml_argv, yaml_argv);
+        assert_eq!(
+            toml_argv,
+            [
+                "send",
+                "--num-iterations",
+                "600",
+                "--only-stats",
+                "--rps",
+                "50",
+                "--total-wallets",
+                "10"
+            ]
+        );
+        assert!(matches!(
+            toml.phases[0].run[0].parse().unwrap(),
+            Commands::Send(_)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_step_is_rejected() {
+        let toml = r#"
+            [[phase]]
+            name = "broken"
+            [[phase.run]]
+            command = "send"
+            rps = 50
+        "#;
+        let scenario: ScenarioFile = toml::from_str(toml).unwrap();
+        assert!(scenario.phases[0].run[0].parse().is_err());
+    }
+}
@@ -1,46 +1,240 @@
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
 use ed25519_dalek::Keypair;
-use nekoton::core::ton_wallet::TransferAction;
+use nekoton::core::ton_wallet::{self, TransferAction, WalletType};
 use nekoton::models::Expiration;
 use nekoton_utils::SimpleClock;
 use ton_abi::sign_with_signature_id;
-use ton_block::{AccountStuff, GlobalCapabilities, MsgAddressInt};
-use ton_types::{BuilderData, SliceData};
+use ton_block::{AccountState, AccountStuff, GlobalCapabilities, MsgAddressInt};
+use ton_types::{BuilderData, SliceData, UInt256};
+
+use crate::wallets::WalletKind;
+
+/// Identical messages can only be built within the same second, hashes older than this are
+/// forgotten.
+const DEDUP_WINDOW: Duration = Duration::from_secs(10);
+
+/// WalletV3 transfers built ahead of the on-chain seqno before the state is read again.
+const MAX_SEQNO_AHEAD: usize = 32;
+
+/// Comment text is stored in the root cell, 127 bytes minus the function id.
+const MAX_COMMENT_LEN: usize = 123;
+
+struct CommentTag {
+    run_id: String,
+    instance_id: String,
+}
+
+static COMMENT_TAG: OnceLock<CommentTag> = OnceLock::new();
+
+/// Makes [`transfer_payload`] attach `nekroddos:<run>:<instance>:<wallet>:<seq>` comments.
+pub fn enable_comments(run_id: String, instance_id: String) -> anyhow::Result<()> {
+    let longest = format!("nekroddos:{run_id}:{instance_id}:{}:{}", u32::MAX, u64::MAX);
+    if longest.len() > MAX_COMMENT_LEN {
+        anyhow::bail!("Run id and instance id are too long for a transfer comment");
+    }
+    log::info!("Tagging transfers with run id {run_id}, instance id {instance_id}");
+    COMMENT_TAG
+        .set(CommentTag {
+            run_id,
+            instance_id,
+        })
+        .map_err(|_| anyhow::anyhow!("Comments are already enabled"))
+}
+
+pub fn comment_run_id() -> Option<&'static str> {
+    COMMENT_TAG.get().map(|tag| tag.run_id.as_str())
+}
+
+/// Body of a plain transfer: the attribution comment when enabled, empty otherwise.
+pub fn transfer_payload(wallet: u32, seq: u64) -> anyhow::Result<BuilderData> {
+    let Some(tag) = COMMENT_TAG.get() else {
+        return Ok(BuilderData::new());
+    };
+    let text = format!(
+        "nekroddos:{}:{}:{wallet}:{seq}",
+        tag.run_id, tag.instance_id
+    );
+    comment(&text)
+}
+
+/// Standard comment cell: zero function id followed by the UTF-8 text.
+fn comment(text: &str) -> anyhow::Result<BuilderData> {
+    let mut cell = BuilderData::new();
+    cell.append_u32(0)?;
+    cell.append_raw(text.as_bytes(), text.len() * 8)?;
+    Ok(cell)
+}
+
+/// Message dropped because an identical one was already broadcast by this process, counted by
+/// [`RunStats::record_send`](crate::report::RunStats::record_send).
+#[derive(Debug)]
+pub struct DuplicateMessage {
+    pub from: MsgAddressInt,
+}
+
+impl std::fmt::Display for DuplicateMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Duplicate message from {}, not broadcasting it",
+            self.from
+        )
+    }
+}
+
+impl std::error::Error for DuplicateMessage {}
+
+/// Outgoing transfer of a wallet, several of them can share one external message.
+pub struct Transfer {
+    pub destination: MsgAddressInt,
+    pub amount: u64,
+    pub payload: BuilderData,
+}
 
 #[allow(clippy::too_many_arguments)]
 pub async fn send(
     client: &everscale_rpc_client::RpcClient,
     signer: &Keypair,
+    kind: WalletKind,
     from: MsgAddressInt,
     payload: BuilderData,
     destination: MsgAddressInt,
     amount: u64,
     state: &AccountStuff,
 ) -> anyhow::Result<()> {
+    let transfer = Transfer {
+        destination,
+        amount,
+        payload,
+    };
+    send_batch(client, signer, kind, from, vec![transfer], state).await
+}
+
+/// External message accepted for broadcast.
+pub struct SentMessage {
+    pub hash: UInt256,
+    pub expire_at: u32,
+}
+
+/// Packs all `transfers` into a single signed external message.
+pub async fn send_batch(
+    client: &everscale_rpc_client::RpcClient,
+    signer: &Keypair,
+    kind: WalletKind,
+    from: MsgAddressInt,
+    transfers: Vec<Transfer>,
+    state: &AccountStuff,
+) -> anyhow::Result<()> {
+    send_transfers(client, signer, kind, from, transfers, state).await?;
+    Ok(())
+}
+
+/// Same as [`send_batch`] but returns the message hash, e.g. to wait for its transaction.
+pub async fn send_transfers(
+    client: &everscale_rpc_client::RpcClient,
+    signer: &Keypair,
+    kind: WalletKind,
+    from: MsgAddressInt,
+    transfers: Vec<Transfer>,
+    state: &AccountStuff,
+) -> anyhow::Result<SentMessage> {
+    let result = sign_and_broadcast(client, signer, kind, from.clone(), transfers, state).await;
+    if result.is_err() && kind == WalletKind::WalletV3 {
+        // The seqno offset was taken by a message that never made it.
+        forget_wallet_v3_state(&from);
+    }
+    result
+}
+
+async fn sign_and_broadcast(
+    client: &everscale_rpc_client::RpcClient,
+    signer: &Keypair,
+    kind: WalletKind,
+    from: MsgAddressInt,
+    transfers: Vec<Transfer>,
+    state: &AccountStuff,
+) -> anyhow::Result<SentMessage> {
     use tokio::sync::OnceCell;
 
     static SIGN_ID: OnceCell<Option<i32>> = OnceCell::const_new();
 
-    let gift = nekoton::core::ton_wallet::Gift {
-        flags: 3,
-        bounce: false,
-        destination,
-        amount: amount.into(),
-        body: Some(SliceData::load_builder(payload)?),
-        state_init: None,
-    };
+    if transfers.is_empty() || transfers.len() > kind.max_gifts() {
+        anyhow::bail!(
+            "{kind:?} sends 1 to {} transfers per message, got {}",
+            kind.max_gifts(),
+            transfers.len()
+        );
+    }
+    let mut gifts = Vec::with_capacity(transfers.len());
+    for transfer in transfers {
+        // Plain transfers get a unique tag, otherwise equal gifts sent within the same
+        // millisecond produce the same external message and the node drops all but one.
+        let payload = transfer.payload;
+        let payload = if payload.bits_used() == 0 && payload.references_used() == 0 {
+            unique_tag()?
+        } else {
+            payload
+        };
+        gifts.push(nekoton::core::ton_wallet::Gift {
+            flags: 3,
+            bounce: false,
+            destination: transfer.destination,
+            amount: transfer.amount.into(),
+            body: Some(SliceData::load_builder(payload)?),
+            state_init: None,
+        });
+    }
 
-    let now = nekoton_utils::now_sec_u64() as u32 + 60;
+    let now =
+        nekoton_utils::now_sec_u64() as u32 + crate::archive::message_ttl().as_secs() as u32;
 
-    let message = nekoton::core::ton_wallet::ever_wallet::prepare_transfer(
-        &SimpleClock,
-        &signer.public,
-        state,
-        from.clone(),
-        vec![gift],
-        Expiration::Timestamp(now),
-    )?;
+    let expiration = Expiration::Timestamp(now);
+    let message = match kind.wallet_type() {
+        WalletType::EverWallet => ton_wallet::ever_wallet::prepare_transfer(
+            &SimpleClock,
+            &signer.public,
+            state,
+            from.clone(),
+            gifts,
+            expiration,
+        )?,
+        WalletType::WalletV3 => {
+            let (state, seqno_offset) = wallet_v3_state(client, &from, state, now).await?;
+            ton_wallet::wallet_v3::prepare_transfer(
+                &SimpleClock,
+                &signer.public,
+                &state,
+                seqno_offset,
+                gifts,
+                expiration,
+            )?
+        }
+        WalletType::HighloadWalletV2 => ton_wallet::highload_wallet_v2::prepare_transfer(
+            &SimpleClock,
+            &signer.public,
+            state,
+            gifts,
+            expiration,
+        )?,
+        WalletType::Multisig(multisig_type) => ton_wallet::multisig::prepare_transfer(
+            &SimpleClock,
+            multisig_type,
+            &signer.public,
+            false,
+            from.clone(),
+            gifts.remove(0),
+            expiration,
+        )?,
+        other => anyhow::bail!("Unsupported wallet type {other:?}"),
+    };
     let message = match message {
-        TransferAction::DeployFirst => panic!("DeployFirst not supported"),
+        TransferAction::DeployFirst => {
+            anyhow::bail!("{kind:?} wallet {from} must be deployed before sending")
+        }
         TransferAction::Sign(m) => m,
     };
 
@@ -61,7 +255,232 @@ pub async fn send(
     let signature = sign_with_signature_id(signer, message.hash(), *sign_id);
     let signed_message = message.sign(&signature.to_bytes()).unwrap().message;
 
-    client.broadcast_message(signed_message).await?;
+    let hash = signed_message.hash()?;
+    if !remember_hash(hash.clone()) {
+        return Err(DuplicateMessage { from }.into());
+    }
 
-    Ok(())
+    crate::archive::broadcast(client, signed_message).await?;
+    crate::tracker::track(hash.clone(), now);
+
+    Ok(SentMessage {
+        hash,
+        expire_at: now,
+    })
+}
+
+/// WalletV3 state and the transfers built on top of it. `None` after a failed send, the state
+/// is read again then.
+static WALLET_V3_STATES: Mutex<BTreeMap<MsgAddressInt, Option<(AccountStuff, PendingSeqnos)>>> =
+    Mutex::new(BTreeMap::new());
+
+/// State and seqno offset for the next WalletV3 transfer expiring at `expire_at`, starting from
+/// the state of the caller. The state is read again after a failed send, once the oldest
+/// pending transfer expired or when [`MAX_SEQNO_AHEAD`] transfers are pending.
+async fn wallet_v3_state(
+    client: &everscale_rpc_client::RpcClient,
+    from: &MsgAddressInt,
+    state: &AccountStuff,
+    expire_at: u32,
+) -> anyhow::Result<(AccountStuff, u32)> {
+    let now = nekoton_utils::now_sec_u64() as u32;
+    let refresh = match WALLET_V3_STATES.lock().unwrap().get_mut(from) {
+        Some(Some((cached, pending))) if !pending.is_stale(now) => {
+            return Ok((cached.clone(), pending.push(expire_at)))
+        }
+        Some(_) => true,
+        None => false,
+    };
+    let state = if refresh {
+        crate::endpoints::call(client, |client| async move {
+            client.get_contract_state(from, None).await
+        })
+        .await?
+        .map(|s| s.account)
+        .unwrap_or_else(|| state.clone())
+    } else {
+        state.clone()
+    };
+    let seqno = wallet_v3_seqno(&state).unwrap_or_default();
+
+    let mut states = WALLET_V3_STATES.lock().unwrap();
+    let entry = states.entry(from.clone()).or_insert(None);
+    match entry {
+        // Another transfer has already read a newer state meanwhile.
+        Some((_, pending)) if pending.base > seqno => {}
+        Some((cached, pending)) => {
+            *cached = state;
+            pending.rebase(seqno, now);
+        }
+        None => *entry = Some((state, PendingSeqnos::new(seqno))),
+    }
+    let (cached, pending) = entry.as_mut().expect("set above");
+    Ok((cached.clone(), pending.push(expire_at)))
+}
+
+fn wallet_v3_seqno(account: &AccountStuff) -> Option<u32> {
+    match &account.storage.state {
+        AccountState::AccountActive { state_init } => {
+            SliceData::load_cell(state_init.data.clone()?)
+                .ok()?
+                .get_next_u32()
+                .ok()
+        }
+        _ => None,
+    }
+}
+
+/// Expirations of the transfers sent with seqnos `base..` that have not executed yet as far as
+/// the cached state knows. A transfer that never executes blocks every seqno after it.
+struct PendingSeqnos {
+    base: u32,
+    expirations: VecDeque<u32>,
+}
+
+impl PendingSeqnos {
+    fn new(base: u32) -> Self {
+        Self {
+            base,
+            expirations: VecDeque::new(),
+        }
+    }
+
+    /// Takes the next seqno, returns its offset from `base`.
+    fn push(&mut self, expire_at: u32) -> u32 {
+        self.expirations.push_back(expire_at);
+        self.expirations.len() as u32 - 1
+    }
+
+    /// The offset is too far ahead or the oldest transfer either executed or never will.
+    fn is_stale(&self, now: u32) -> bool {
+        self.expirations.len() >= MAX_SEQNO_AHEAD || self.oldest_expired(now)
+    }
+
+    fn oldest_expired(&self, now: u32) -> bool {
+        matches!(self.expirations.front(), Some(&expire_at) if expire_at <= now)
+    }
+
+    /// Moves to the on-chain `seqno`, dropping the executed transfers. When the oldest remaining
+    /// one has expired, the ones after it are dead too and the offset starts over.
+    fn rebase(&mut self, seqno: u32, now: u32) {
+        let executed = (seqno - self.base) as usize;
+        let executed = executed.min(self.expirations.len());
+        self.expirations.drain(..executed);
+        if self.oldest_expired(now) {
+            self.expirations.clear();
+        }
+        self.base = seqno;
+    }
+}
+
+fn forget_wallet_v3_state(wallet: &MsgAddressInt) {
+    WALLET_V3_STATES.lock().unwrap().insert(wallet.clone(), None);
+}
+
+/// Binary comment: zero function id, random run id and a process-wide sequence number.
+fn unique_tag() -> anyhow::Result<BuilderData> {
+    static RUN_ID: OnceLock<u32> = OnceLock::new();
+    static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+    let mut tag = BuilderData::new();
+    tag.append_u32(0)?;
+    tag.append_u32(*RUN_ID.get_or_init(rand::random))?;
+    tag.append_u64(SEQUENCE.fetch_add(1, Ordering::Relaxed))?;
+    Ok(tag)
+}
+
+/// Returns `false` if the hash was already seen during the last [`DEDUP_WINDOW`].
+fn remember_hash(hash: UInt256) -> bool {
+    static RECENT: Mutex<Option<RecentHashes>> = Mutex::new(None);
+
+    RECENT
+        .lock()
+        .unwrap()
+        .get_or_insert_with(|| RecentHashes::new(DEDUP_WINDOW))
+        .insert(hash, Instant::now())
+}
+
+/// Two generations of hashes, rotated every `window` so memory stays bounded.
+struct RecentHashes {
+    window: Duration,
+    current: HashSet<UInt256>,
+    previous: HashSet<UInt256>,
+    rotated_at: Option<Instant>,
+}
+
+impl RecentHashes {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            current: HashSet::new(),
+            previous: HashSet::new(),
+            rotated_at: None,
+        }
+    }
+
+    fn insert(&mut self, hash: UInt256, now: Instant) -> bool {
+        let rotated_at = *self.rotated_at.get_or_insert(now);
+        if now.duration_since(rotated_at) >= self.window {
+            self.previous = std::mem::take(&mut self.current);
+            self.rotated_at = Some(now);
+        }
+        if self.previous.contains(&hash) {
+            return false;
+        }
+        self.current.insert(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comment() {
+        let cell = comment("nekroddos:run:0:1:2").unwrap().into_cell().unwrap();
+        let mut slice = SliceData::load_cell(cell).unwrap();
+        assert_eq!(slice.get_next_u32().unwrap(), 0);
+        let text = slice.get_next_bytes(slice.remaining_bits() / 8).unwrap();
+        assert_eq!(text, b"nekroddos:run:0:1:2");
+    }
+
+    #[test]
+    fn test_recent_hashes() {
+        let start = Instant::now();
+        let mut recent = RecentHashes::new(Duration::from_secs(10));
+        let a = UInt256::from([1; 32]);
+        let b = UInt256::from([2; 32]);
+
+        assert!(recent.insert(a, start));
+        assert!(!recent.insert(a, start + Duration::from_secs(5)));
+        // Rotated once, still remembered by the previous generation.
+        assert!(!recent.insert(a, start + Duration::from_secs(12)));
+        assert!(recent.insert(b, start + Duration::from_secs(12)));
+        // Rotated twice, forgotten.
+        assert!(recent.insert(a, start + Duration::from_secs(25)));
+    }
+
+    #[test]
+    fn test_pending_seqnos() {
+        let mut pending = PendingSeqnos::new(5);
+        assert_eq!(pending.push(100), 0);
+        assert_eq!(pending.push(101), 1);
+        assert!(!pending.is_stale(99));
+
+        // Seqno 5 was accepted but never executed, seqno 6 can't execute after it.
+        assert!(pending.is_stale(100));
+        pending.rebase(5, 100);
+        assert_eq!(pending.push(130), 0);
+        assert_eq!(pending.push(131), 1);
+        assert_eq!(pending.push(132), 2);
+
+        // Seqnos 5 and 6 executed, 7 is still in flight.
+        pending.rebase(7, 110);
+        assert_eq!(pending.push(140), 1);
+
+        while pending.expirations.len() < MAX_SEQNO_AHEAD {
+            pending.push(150);
+        }
+        assert!(pending.is_stale(110));
+    }
 }
@@ -1,25 +1,25 @@
-use crate::abi::{get_wallet, GetWalletFunctionInput, GetWalletFunctionOutput};
-use crate::models::GenericDeploymentInfo;
+use crate::preflight::{self, SenderPlan};
+use crate::report::RunStats;
+use crate::send::{self, Transfer};
 use crate::util::TestEnv;
-use crate::{send, Args};
+use crate::wallets::{self, Sender, WalletKind};
+use crate::Args;
 use anyhow::{Context, Result};
 use clap::Parser;
 use ed25519_dalek::Keypair;
 use everscale_rpc_client::RpcClient;
 use governor::Jitter;
-use nekoton_abi::{FunctionExt, PackAbiPlain, UnpackAbiPlain};
-use nekoton_utils::SimpleClock;
 use rand::prelude::{SliceRandom, StdRng};
 use rand::{Rng, SeedableRng};
+use serde::Serialize;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::fs::File;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use ton_block::MsgAddressInt;
-use ton_types::{BuilderData, UInt256};
 
-#[derive(Parser, Debug, Clone)]
+#[derive(Parser, Debug, Clone, Serialize)]
 pub struct SendToTargetsArgs {
     #[clap(short, long)]
     total_wallets: u32,
@@ -31,6 +31,10 @@ pub struct SendToTargetsArgs {
     amount: u64,
     #[clap(short, long)]
     targets_file: PathBuf,
+
+    #[clap(long, default_value = "1")]
+    /// Transfers packed into every external message, each to its own random destination
+    gifts_per_message: u32,
 }
 
 pub async fn run(
@@ -38,51 +42,15 @@ pub async fn run(
     common_args: Args,
     key_pair: Arc<Keypair>,
     client: RpcClient,
+    stats: RunStats,
 ) -> Result<()> {
-    let base_deployments_path = common_args.project_root.join("deployments");
-    let network_deployments_path = if let Some(network_name) = &common_args.network {
-        base_deployments_path.join(network_name)
-    } else {
-        base_deployments_path
-    };
-
-    if common_args.network.is_some() && !network_deployments_path.is_dir() {
-        return Err(anyhow::anyhow!(
-            "Specified network deployment directory not found: {:?}",
-            network_deployments_path
-        ));
-    }
-
-    log::info!("Using deployments path: {:?}", network_deployments_path);
     log::info!("Targets file: {:?}", args.targets_file);
 
-    // Find factory ABI
-    let factory_abi = walkdir::WalkDir::new(&network_deployments_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| e.path().extension().map(|e| e == "json").unwrap_or(false))
-        .find(|e| {
-            e.path()
-                .file_name()
-                .and_then(|n| n.to_str())
-                .map(|n| n.to_lowercase().contains("factory"))
-                .unwrap_or(false)
-        })
-        .context("No factory abi")?;
-    let factory: GenericDeploymentInfo =
-        serde_json::from_slice(&std::fs::read(factory_abi.path())?)?;
-
     // Get sender wallets
-    let sender_wallets = get_wallets(
-        client.clone(),
-        &factory.address,
-        args.total_wallets,
-        key_pair.public.to_bytes(),
-    )
-    .await
-    .context("Failed to get sender wallets")?;
+    let sender_wallets =
+        wallets::load_senders(&common_args, &client, &key_pair, args.total_wallets).await?;
     log::info!("Deployed {} sender wallets", sender_wallets.len());
+    wallets::check_gifts_per_message(&sender_wallets, args.gifts_per_message)?;
 
     // Read target addresses from file
     let target_addresses = read_targets(&args.targets_file).await?;
@@ -91,8 +59,28 @@ pub async fn run(
     }
     log::info!("Loaded {} target addresses", target_addresses.len());
 
+    let plan: Vec<_> = sender_wallets
+        .iter()
+        .map(|sender| SenderPlan {
+            wallet: sender.address.clone(),
+            messages: args.num_iterations as u64 * args.gifts_per_message as u64,
+            value: args.amount,
+            reserve: 0,
+            deploys_itself: sender.kind == WalletKind::EverWallet,
+        })
+        .collect();
+    preflight::check(&client, &common_args, &plan).await?;
+
     // Run the DDoS jobs
-    spawn_ddos_jobs(&args, client, sender_wallets, target_addresses, common_args, key_pair).await?;
+    spawn_ddos_jobs(
+        &args,
+        client,
+        sender_wallets,
+        target_addresses,
+        common_args,
+        stats,
+    )
+    .await?;
 
     Ok(())
 }
@@ -121,10 +109,10 @@ async fn read_targets(path: &PathBuf) -> Result<Vec<MsgAddressInt>> {
 async fn spawn_ddos_jobs(
     args: &SendToTargetsArgs,
     client: RpcClient,
-    sender_wallets: Vec<MsgAddressInt>,
+    sender_wallets: Vec<Sender>,
     target_addresses: Vec<MsgAddressInt>,
     common_args: Args,
-    key_pair: Arc<Keypair>,
+    stats: RunStats,
 ) -> Result<()> {
     let test_env = TestEnv::new(
         args.num_iterations,
@@ -133,27 +121,20 @@ async fn spawn_ddos_jobs(
         client,
         common_args.seed,
         common_args.clone(),
+        stats,
     );
 
     log::info!("Spawning ddos jobs for {} sender wallets", sender_wallets.len());
     let target_addresses = Arc::new(target_addresses);
 
     let mut rng = StdRng::seed_from_u64(test_env.seed.unwrap_or_else(rand::random));
-    for sender in &sender_wallets {
+    for sender in sender_wallets {
         let seed = rng.gen();
         let rng = StdRng::seed_from_u64(seed); // Each job should have its own rng
         let env = test_env.clone();
         let targets = target_addresses.clone();
-        let key_pair = key_pair.clone();
-        let amount = args.amount;
-        tokio::spawn(ddos_job(
-            env,
-            sender.clone(),
-            targets,
-            key_pair,
-            rng,
-            amount,
-        ));
+        let (amount, gifts) = (args.amount, args.gifts_per_message);
+        tokio::spawn(ddos_job(env, sender, targets, rng, amount, gifts));
     }
     log::info!("All jobs spawned");
 
@@ -166,44 +147,60 @@ async fn spawn_ddos_jobs(
 
 async fn ddos_job(
     test_env: TestEnv,
-    from_wallet: MsgAddressInt,
+    from_wallet: Sender,
     target_addresses: Arc<Vec<MsgAddressInt>>,
-    signer: Arc<Keypair>,
     mut rng: StdRng,
     amount: u64,
+    gifts_per_message: u32,
 ) -> Result<()> {
     let jitter = Jitter::new(Duration::from_millis(1), Duration::from_millis(50));
     let state = test_env
         .client
-        .get_contract_state(&from_wallet, None)
+        .get_contract_state(&from_wallet.address, None)
         .await?
-        .unwrap_or_else(|| panic!("No state for {from_wallet}"))
+        .unwrap_or_else(|| {
+            panic!(
+                "No state for {}, deploy it with `provision wallets`",
+                from_wallet.address
+            )
+        })
         .account;
 
     for _ in 1..=test_env.num_iterations {
-        let random_target = target_addresses.choose(&mut rng).unwrap().clone();
+        let mut transfers = Vec::with_capacity(gifts_per_message as usize);
+        for _ in 0..gifts_per_message {
+            transfers.push(Transfer {
+                destination: target_addresses.choose(&mut rng).unwrap().clone(),
+                amount,
+                payload: from_wallet.transfer_payload()?,
+            });
+        }
 
         test_env.rate_limiter.until_ready_with_jitter(jitter).await;
         let h = {
             let client = test_env.client.clone();
-            let counter = test_env.counter.clone();
-            let signer = signer.clone();
+            let stats = test_env.stats.clone();
             let from = from_wallet.clone();
             let state = state.clone();
 
             tokio::spawn(async move {
-                send::send(
+                let count = transfers.len() as u64;
+                stats.record_built();
+                let start = Instant::now();
+                let result = send::send_batch(
                     &client,
-                    &signer,
-                    from,
-                    BuilderData::new(),
-                    random_target,
-                    amount,
+                    &from.keypair,
+                    from.kind,
+                    from.address,
+                    transfers,
                     &state,
                 )
-                .await
-                .unwrap();
-                counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                .await;
+                stats.record_send(start.elapsed(), &result);
+                match result {
+                    Ok(()) => stats.record_transfers(count),
+                    Err(e) => log::error!("Failed to send: {:?}", e),
+                }
             })
         };
         if !test_env.args.no_wait {
@@ -215,31 +212,3 @@ async fn ddos_job(
     Ok(())
 }
 
-async fn get_wallets(
-    client: RpcClient,
-    factory: &MsgAddressInt,
-    num_wallets: u32,
-    pubkey: [u8; 32],
-) -> Result<Vec<MsgAddressInt>> {
-    let method = get_wallet();
-    let state = client
-        .get_contract_state(factory, None)
-        .await?
-        .context("No state")?;
-
-    let mut senders = Vec::new();
-    for i in 0..num_wallets {
-        let tokens = GetWalletFunctionInput {
-            index: i as _,
-            public_key: UInt256::from(pubkey),
-        }
-        .pack();
-        let result = method.run_local(&SimpleClock, state.account.clone(), &tokens)?;
-        let tokens = result.tokens.context("No tokens")?;
-        let addr: GetWalletFunctionOutput = tokens.unpack()?;
-        senders.push(addr.receiver);
-    }
-
-    Ok(senders)
-}
-
@@ -1,25 +1,28 @@
-use crate::abi::{get_wallet, GetWalletFunctionInput, GetWalletFunctionOutput};
-use crate::models::GenericDeploymentInfo;
+use crate::preflight::{self, SenderPlan};
+use crate::report::RunStats;
+use crate::send::{self, Transfer};
 use crate::util::TestEnv;
-use crate::{send, Args};
-use anyhow::{Context, Result};
+use crate::wallets::{self, Sender, WalletKind};
+use crate::Args;
+use anyhow::Result;
 use clap::Parser;
 use ed25519_dalek::Keypair;
 use everscale_rpc_client::RpcClient;
 use governor::Jitter;
-use nekoton_abi::{FunctionExt, PackAbiPlain, UnpackAbiPlain};
-use nekoton_utils::SimpleClock;
 use rand::prelude::{SliceRandom, StdRng};
 use rand::{Rng, SeedableRng};
+use serde::Serialize;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::mpsc::UnboundedSender;
 use ton_block::MsgAddressInt;
-use ton_types::{BuilderData, UInt256};
 
-#[derive(Parser, Debug, Clone)]
+/// Value of every transfer between the test wallets.
+const TRANSFER_AMOUNT: u64 = 1_000_000;
+
+#[derive(Parser, Debug, Clone, Serialize)]
 pub struct SendTestArgs {
     #[clap(short, long)]
     total_wallets: u32,
@@ -33,53 +36,34 @@ pub struct SendTestArgs {
 
     #[clap(long)]
     log_file: Option<PathBuf>,
+
+    #[clap(long, default_value = "1")]
+    /// Transfers packed into every external message, each to its own random destination
+    gifts_per_message: u32,
 }
 pub async fn run(
     swap_args: SendTestArgs,
     common_args: Args,
     key_pair: Arc<Keypair>,
     client: RpcClient,
+    stats: RunStats,
 ) -> Result<()> {
-    let base_deployments_path = common_args.project_root.join("deployments");
-    let network_deployments_path = if let Some(network_name) = &common_args.network {
-        base_deployments_path.join(network_name)
-    } else {
-        base_deployments_path
-    };
-
-    if common_args.network.is_some() && !network_deployments_path.is_dir() {
-        return Err(anyhow::anyhow!(
-            "Specified network deployment directory not found: {:?}",
-            network_deployments_path
-        ));
-    }
-
-    log::info!("Using deployments path: {:?}", network_deployments_path);
-
-    let factory_abi = walkdir::WalkDir::new(&network_deployments_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| e.path().extension().map(|e| e == "json").unwrap_or(false))
-        .find(|e| {
-            e.path()
-                .file_name()
-                .and_then(|n| n.to_str())
-                .map(|n| n.to_lowercase().contains("factory"))
-                .unwrap_or(false)
+    let mut senders =
+        wallets::load_senders(&common_args, &client, &key_pair, swap_args.total_wallets).await?;
+    senders.sort_by(|a, b| a.address.cmp(&b.address));
+    wallets::check_gifts_per_message(&senders, swap_args.gifts_per_message)?;
+
+    let plan: Vec<_> = senders
+        .iter()
+        .map(|sender| SenderPlan {
+            wallet: sender.address.clone(),
+            messages: swap_args.num_iterations as u64 * swap_args.gifts_per_message as u64,
+            value: TRANSFER_AMOUNT,
+            reserve: 0,
+            deploys_itself: sender.kind == WalletKind::EverWallet,
         })
-        .context("No factory abi")?;
-    let factory: GenericDeploymentInfo =
-        serde_json::from_slice(&std::fs::read(factory_abi.path())?)?;
-    let mut recievers = get_wallets(
-        client.clone(),
-        &factory.address,
-        swap_args.total_wallets,
-        key_pair.public.to_bytes(),
-    )
-    .await
-    .context("Failed to get wallets")?;
-    recievers.sort();
+        .collect();
+    preflight::check(&client, &common_args, &plan).await?;
 
     let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<String>();
     if let Some(log_file) = swap_args.log_file.clone() {
@@ -93,7 +77,7 @@ pub async fn run(
         });
     }
 
-    spawn_ddos_jobs(&swap_args, client, recievers, common_args, key_pair, tx).await?;
+    spawn_ddos_jobs(&swap_args, client, senders, common_args, tx, stats).await?;
 
     Ok(())
 }
@@ -101,45 +85,39 @@ pub async fn run(
 async fn spawn_ddos_jobs(
     args: &SendTestArgs,
     client: RpcClient,
-    recievers: Vec<MsgAddressInt>,
+    senders: Vec<Sender>,
     common_args: Args,
-    key_pair: Arc<Keypair>,
     tx: UnboundedSender<String>,
+    stats: RunStats,
 ) -> Result<()> {
     let test_env = TestEnv::new(
         args.num_iterations,
         args.rps,
-        args.total_wallets as usize,
+        senders.len(),
         client,
         common_args.seed,
         common_args.clone(),
+        stats,
     );
 
     if args.only_stats {
-        test_env.set_counter(args.num_iterations as u64 * recievers.len() as u64);
+        test_env.set_counter(args.num_iterations as u64 * senders.len() as u64);
         // print_stats(recievers, &test_env).await;
         return Ok(());
     }
 
-    log::info!("Spawning ddos jobs for {} recievers", recievers.len());
-    let receivers = Arc::new(recievers);
+    log::info!("Spawning ddos jobs for {} recievers", senders.len());
+    let receivers = Arc::new(senders.iter().map(|s| s.address.clone()).collect::<Vec<_>>());
 
     let mut rng = StdRng::seed_from_u64(test_env.seed.unwrap_or_else(rand::random));
-    for receiver in receivers.iter() {
+    for sender in senders {
         let seed = rng.gen();
         let rng = StdRng::seed_from_u64(seed); // Each job should have its own rng
         let env = test_env.clone();
         let receivers = receivers.clone();
-        let key_pair = key_pair.clone();
         let tx = tx.clone();
-        tokio::spawn(ddos_job(
-            env,
-            receiver.clone(),
-            receivers,
-            key_pair,
-            rng,
-            tx,
-        ));
+        let gifts = args.gifts_per_message;
+        tokio::spawn(ddos_job(env, sender, receivers, rng, tx, gifts));
     }
     log::info!("All jobs spawned");
 
@@ -152,47 +130,69 @@ async fn spawn_ddos_jobs(
 
 async fn ddos_job(
     test_env: TestEnv,
-    from: MsgAddressInt,
+    from: Sender,
     wallets: Arc<Vec<MsgAddressInt>>,
-    signer: Arc<Keypair>,
     mut rng: StdRng,
     tx: UnboundedSender<String>,
+    gifts_per_message: u32,
 ) -> Result<()> {
     let jitter = Jitter::new(Duration::from_millis(1), Duration::from_millis(50));
     let state = test_env
         .client
-        .get_contract_state(&from, None)
+        .get_contract_state(&from.address, None)
         .await?
-        .unwrap_or_else(|| panic!("No state for {from}"))
+        .unwrap_or_else(|| {
+            panic!(
+                "No state for {}, deploy it with `provision wallets`",
+                from.address
+            )
+        })
         .account;
 
     for _ in 1..=test_env.num_iterations {
-        let rand_dst = wallets.choose(&mut rng).unwrap().clone();
+        let mut transfers = Vec::with_capacity(gifts_per_message as usize);
+        for _ in 0..gifts_per_message {
+            transfers.push(Transfer {
+                destination: wallets.choose(&mut rng).unwrap().clone(),
+                amount: TRANSFER_AMOUNT,
+                payload: from.transfer_payload()?,
+            });
+        }
 
         test_env.rate_limiter.until_ready_with_jitter(jitter).await;
         let h = {
             let client = test_env.client.clone();
-            let counter = test_env.counter.clone();
-            let signer = signer.clone();
+            let stats = test_env.stats.clone();
             let from = from.clone();
             let state = state.clone();
             let tx = tx.clone();
 
             tokio::spawn(async move {
-                let addr_str = rand_dst.to_string();
-                send::send(
+                let addrs: Vec<_> = transfers
+                    .iter()
+                    .map(|t| t.destination.to_string())
+                    .collect();
+                stats.record_built();
+                let start = Instant::now();
+                let result = send::send_batch(
                     &client,
-                    &signer,
-                    from,
-                    BuilderData::new(),
-                    rand_dst,
-                    1_000_000,
+                    &from.keypair,
+                    from.kind,
+                    from.address,
+                    transfers,
                     &state,
                 )
-                .await
-                .unwrap();
-                let _ = tx.send(addr_str);
-                counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                .await;
+                stats.record_send(start.elapsed(), &result);
+                match result {
+                    Ok(()) => {
+                        stats.record_transfers(addrs.len() as u64);
+                        for addr in addrs {
+                            let _ = tx.send(addr);
+                        }
+                    }
+                    Err(e) => log::error!("Failed to send: {:?}", e),
+                }
             })
         };
         if !test_env.args.no_wait {
@@ -204,31 +204,3 @@ async fn ddos_job(
     Ok(())
 }
 
-async fn get_wallets(
-    client: RpcClient,
-    factory: &MsgAddressInt,
-    num_wallets: u32,
-    pubkey: [u8; 32],
-) -> Result<Vec<MsgAddressInt>> {
-    let method = get_wallet();
-    let state = client
-        .get_contract_state(factory, None)
-        .await?
-        .context("No state")?;
-
-    let mut recipients = Vec::new();
-    for i in 0..num_wallets {
-        let tokens = GetWalletFunctionInput {
-            index: i as _,
-            public_key: UInt256::from(pubkey),
-        }
-        .pack();
-        let result = method.run_local(&SimpleClock, state.account.clone(), &tokens)?;
-        let tokens = result.tokens.context("No tokens")?;
-        let addr: GetWalletFunctionOutput = tokens.unpack()?;
-        recipients.push(addr.receiver);
-    }
-
-    Ok(recipients)
-}
-
@@ -0,0 +1,312 @@
+//! SLA rules checked against the report at the end of a run, set with `--sla`.
+//!
+//! A rule is `<metric><op><value>`, e.g. `p95<2s`, `error_rate<0.5%` or `rps>=95%`:
+//!
+//! - `p50`, `p95`, `p99`, `max`: latency percentiles, values are durations like `800ms`
+//! - `error_rate`: failed out of attempted messages, a fraction or a percentage
+//! - `rps`: average messages per second while sending, absolute or a percentage of the target
+//!
+//! Scenarios check every phase separately. Rules without data, e.g. latency of a run that sent
+//! nothing, are reported as `n/a` and fail the run unless `--sla-allow-missing` is set.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::report::RunReport;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Metric {
+    P50,
+    P95,
+    P99,
+    Max,
+    ErrorRate,
+    Rps,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    text: String,
+    metric: Metric,
+    op: Op,
+    /// Seconds for latencies, a fraction for the error rate, messages per second or a fraction
+    /// of the target for rps.
+    threshold: f64,
+    of_target: bool,
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let text: String = s.split_whitespace().collect();
+        let op_at = text
+            .find(['<', '>'])
+            .with_context(|| format!("SLA rule `{s}` has no comparison"))?;
+        let (metric, rest) = text.split_at(op_at);
+        let (op, value) = match rest.split_at(1) {
+            ("<", value) => match value.strip_prefix('=') {
+                Some(value) => (Op::Le, value),
+                None => (Op::Lt, value),
+            },
+            (_, value) => match value.strip_prefix('=') {
+                Some(value) => (Op::Ge, value),
+                None => (Op::Gt, value),
+            },
+        };
+
+        let metric = match metric {
+            "p50" => Metric::P50,
+            "p95" => Metric::P95,
+            "p99" => Metric::P99,
+            "max" => Metric::Max,
+            "error_rate" => Metric::ErrorRate,
+            "rps" => Metric::Rps,
+            _ => anyhow::bail!(
+                "Unknown SLA metric `{metric}`, expected p50, p95, p99, max, error_rate or rps"
+            ),
+        };
+        let percent = value.strip_suffix('%');
+        let threshold = match (metric, percent) {
+            (Metric::ErrorRate | Metric::Rps, Some(percent)) => percent.parse::<f64>()? / 100.0,
+            (Metric::ErrorRate | Metric::Rps, None) => value.parse()?,
+            (_, _) => humantime::parse_duration(value)
+                .with_context(|| format!("Invalid duration in SLA rule `{s}`"))?
+                .as_secs_f64(),
+        };
+
+        Ok(Self {
+            metric,
+            op,
+            threshold,
+            of_target: metric == Metric::Rps && percent.is_some(),
+            text,
+        })
+    }
+}
+
+impl Serialize for Rule {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+impl Rule {
+    fn holds(&self, value: f64) -> bool {
+        match self.op {
+            Op::Lt => value < self.threshold,
+            Op::Le => value <= self.threshold,
+            Op::Gt => value > self.threshold,
+            Op::Ge => value >= self.threshold,
+        }
+    }
+
+    /// Value of the metric in threshold units, `None` without data.
+    fn measure(&self, report: &RunReport) -> Option<f64> {
+        let latency = |ns: fn(&crate::report::LatencyReport) -> u64| {
+            report.latency.as_ref().map(|l| ns(l) as f64 / 1e9)
+        };
+        match self.metric {
+            Metric::P50 => latency(|l| l.p50_ns),
+            Metric::P95 => latency(|l| l.p95_ns),
+            Metric::P99 => latency(|l| l.p99_ns),
+            Metric::Max => latency(|l| l.max_ns),
+            Metric::ErrorRate => {
+                let attempts = report.counts.sent + report.counts.failed;
+                (attempts > 0).then(|| report.counts.failed as f64 / attempts as f64)
+            }
+            Metric::Rps => {
+                let rps = sending_rps(&report.throughput)?;
+                if self.of_target {
+                    report.target_rps.map(|target| rps / target as f64)
+                } else {
+                    Some(rps)
+                }
+            }
+        }
+    }
+
+    fn format_value(&self, value: f64) -> String {
+        match self.metric {
+            Metric::ErrorRate => format!("{:.3}%", value * 100.0),
+            Metric::Rps if self.of_target => format!("{:.1}% of target", value * 100.0),
+            Metric::Rps => format!("{value:.1}"),
+            _ => format!("{:?}", Duration::from_secs_f64(value)),
+        }
+    }
+}
+
+/// Average of the per-second throughput without the idle seconds before the first and after
+/// the last sent message, which belong to setup and confirmation.
+fn sending_rps(throughput: &[u64]) -> Option<f64> {
+    let first = throughput.iter().position(|&n| n > 0)?;
+    let last = throughput.iter().rposition(|&n| n > 0)?;
+    let active = &throughput[first..=last];
+    Some(active.iter().sum::<u64>() as f64 / active.len() as f64)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Verdict {
+    pub rule: String,
+    /// Measured value in the units of the rule, `None` without data.
+    pub value: Option<f64>,
+    pub actual: String,
+    pub passed: bool,
+}
+
+/// Returned by [`crate::run_from`] when the run finished but broke an SLA rule.
+#[derive(Debug)]
+pub struct SlaViolation {
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl std::fmt::Display for SlaViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} of {} SLA checks failed", self.failed, self.total)
+    }
+}
+
+impl std::error::Error for SlaViolation {}
+
+fn evaluate(rules: &[Rule], allow_missing: bool, report: &RunReport) -> Vec<Verdict> {
+    rules
+        .iter()
+        .map(|rule| {
+            let value = rule.measure(report);
+            Verdict {
+                rule: rule.text.clone(),
+                value,
+                actual: value
+                    .map(|v| rule.format_value(v))
+                    .unwrap_or_else(|| "n/a".to_string()),
+                passed: value.map(|v| rule.holds(v)).unwrap_or(allow_missing),
+            }
+        })
+        .collect()
+}
+
+/// Fills the verdicts of the report, or of every phase of a scenario, prints them as a table and
+/// fails with [`SlaViolation`] when a rule is broken or, unless `allow_missing`, has no data.
+pub fn check(rules: &[Rule], allow_missing: bool, report: &mut RunReport) -> Result<()> {
+    if rules.is_empty() {
+        return Ok(());
+    }
+
+    let mut rows = Vec::new();
+    if report.phases.is_empty() {
+        report.sla = evaluate(rules, allow_missing, report);
+        rows.extend(
+            report
+                .sla
+                .iter()
+                .map(|v| (report.command.clone(), v.clone())),
+        );
+    } else {
+        for phase in &mut report.phases {
+            phase.report.sla = evaluate(rules, allow_missing, &phase.report);
+            let scope = format!("{}/{}", phase.phase, phase.report.command);
+            rows.extend(phase.report.sla.iter().map(|v| (scope.clone(), v.clone())));
+        }
+    }
+
+    let width = |f: fn(&(String, Verdict)) -> usize, title: &str| {
+        rows.iter()
+            .map(f)
+            .max()
+            .unwrap_or_default()
+            .max(title.len())
+    };
+    let scope_width = width(|(scope, _)| scope.len(), "RUN");
+    let rule_width = width(|(_, v)| v.rule.len(), "RULE");
+    let actual_width = width(|(_, v)| v.actual.len(), "ACTUAL");
+
+    log::info!("SLA verdict:");
+    log::info!(
+        "  {:scope_width$}  {:rule_width$}  {:actual_width$}  RESULT",
+        "RUN",
+        "RULE",
+        "ACTUAL"
+    );
+    for (scope, verdict) in &rows {
+        let result = match (verdict.passed, verdict.value) {
+            (true, None) => "n/a",
+            (true, _) => "pass",
+            (false, _) => "FAIL",
+        };
+        log::info!(
+            "  {scope:scope_width$}  {:rule_width$}  {:actual_width$}  {result}",
+            verdict.rule,
+            verdict.actual
+        );
+    }
+
+    let failed = rows.iter().filter(|(_, v)| !v.passed).count();
+    if failed > 0 {
+        return Err(SlaViolation {
+            failed,
+            total: rows.len(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_parse_and_hold_rules() {
+        let p95: Rule = "p95 < 2s".parse().unwrap();
+        assert_eq!(
+            (p95.metric, p95.op, p95.threshold),
+            (Metric::P95, Op::Lt, 2.0)
+        );
+        assert!(p95.holds(1.5) && !p95.holds(2.0));
+
+        let errors: Rule = "error_rate<=0.5%".parse().unwrap();
+        assert_eq!(errors.op, Op::Le);
+        assert!(errors.holds(0.005) && !errors.holds(0.006));
+
+        let rps: Rule = "rps>=95%".parse().unwrap();
+        assert!(rps.of_target);
+        assert!(rps.holds(0.95) && !rps.holds(0.9));
+        let rps: Rule = "rps>100".parse().unwrap();
+        assert!(!rps.of_target && rps.threshold == 100.0);
+
+        assert!("p95 2s".parse::<Rule>().is_err());
+        assert!("latency<2s".parse::<Rule>().is_err());
+        assert!("p99<soon".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn test_sending_rps_skips_idle_seconds() {
+        assert_eq!(sending_rps(&[0, 0, 90, 100, 110, 0]), Some(100.0));
+        assert_eq!(sending_rps(&[0, 0]), None);
+    }
+
+    #[test]
+    fn test_rules_without_data_fail_unless_allowed() {
+        let args = crate::Args::try_parse_from(["nekroddos", "-p", ".", "deployments", "check"]);
+        let report = RunReport::new(&args.unwrap());
+        let rules = ["p95<2s".parse().unwrap()];
+
+        let verdicts = evaluate(&rules, false, &report);
+        assert_eq!(verdicts[0].actual, "n/a");
+        assert!(!verdicts[0].passed);
+        assert!(evaluate(&rules, true, &report)[0].passed);
+    }
+}
@@ -1,5 +1,5 @@
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
 use clap::Parser;
@@ -9,15 +9,21 @@ use futures_util::StreamExt;
 use governor::clock::DefaultClock;
 use governor::state::{InMemoryState, NotKeyed};
 use governor::{Jitter, RateLimiter};
+use serde::Serialize;
 use ton_block::AccountStuff;
 
-use crate::models::{
-    EverWalletInfo, GenericDeploymentInfo, PayloadGeneratorsData, PayloadMeta, SendData,
-};
+use crate::deployments::{Deployments, Role};
+use crate::models::{PayloadGeneratorsData, PayloadMeta, SendData};
+use crate::preflight::{self, SenderPlan};
+use crate::report::RunStats;
 use crate::util::TestEnv;
+use crate::wallets::WalletKind;
 use crate::{app_cache, send, Args};
 
-#[derive(Parser, Debug, Clone)]
+/// Value attached to every swap transfer.
+const SWAP_ATTACHED_VALUE: u64 = 3_000_000_000;
+
+#[derive(Parser, Debug, Clone, Serialize)]
 pub struct SwapTestArgs {
     #[clap(short, long)]
     /// total swaps per wallet
@@ -36,44 +42,23 @@ pub async fn run(
     common_args: Args,
     keypair: &Keypair,
     client: RpcClient,
+    stats: RunStats,
 ) -> Result<()> {
     if swap_args.depth < 2 {
         panic!("Depth should be at least 2");
     }
 
-    let base_deployments_path = common_args.project_root.join("deployments");
-    let network_deployments_path = if let Some(network_name) = &common_args.network {
-        base_deployments_path.join(network_name)
-    } else {
-        base_deployments_path
-    };
-
-    if common_args.network.is_some() && !network_deployments_path.is_dir() {
-        return Err(anyhow::anyhow!(
-            "Specified network deployment directory not found: {:?}",
-            network_deployments_path
-        ));
-    }
-
-    let mut recipients = Vec::new();
-    let mut pool_addresses = Vec::new();
-
-    for file in walkdir::WalkDir::new(&network_deployments_path)
+    let deployments = Deployments::load(&common_args)?;
+    let mut recipients: Vec<_> = deployments
+        .all(Role::CommonAccount)?
         .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-        .filter(|e| e.path().extension().map(|e| e == "json").unwrap_or(false))
-    {
-        let filename = file.file_name().to_string_lossy();
-        if filename.contains("commonAccount") {
-            let wallet_info: EverWalletInfo = serde_json::from_slice(&std::fs::read(file.path())?)?;
-            recipients.push(wallet_info.address);
-        }
-        if filename.contains("DexPair") {
-            let info: GenericDeploymentInfo = serde_json::from_slice(&std::fs::read(file.path())?)?;
-            pool_addresses.push(info.address);
-        }
-    }
+        .map(|d| d.address)
+        .collect();
+    let pool_addresses: Vec<_> = deployments
+        .all(Role::DexPair)?
+        .into_iter()
+        .map(|d| d.address)
+        .collect();
 
     log::info!(
         "Found {} wallets and {} pools",
@@ -82,17 +67,8 @@ pub async fn run(
     );
     recipients.sort();
 
-    let app_cache = app_cache::AppCache::new(client.clone(), common_args.seed)
-        .load_states(pool_addresses)
-        .await
-        .load_tokens_and_token_pairs()
-        .await;
-
-    log::info!("Loaded app cache");
-
-    let start = std::time::Instant::now();
     let temp_client = client.clone();
-    let filtered_recipients = futures_util::stream::iter(recipients)
+    let recipients: Vec<_> = futures_util::stream::iter(recipients)
         .map(move |addr| {
             let client = temp_client.clone();
             async move {
@@ -111,12 +87,34 @@ pub async fn run(
             } else {
                 None
             }
-        });
+        })
+        .collect()
+        .await;
 
-    let mut payloads = Vec::new();
-    let mut filtered_recipients = std::pin::pin!(filtered_recipients);
+    // The attached value pays for the swap chain, the change comes back to the wallet.
+    let plan: Vec<_> = recipients
+        .iter()
+        .map(|recipient| SenderPlan {
+            wallet: recipient.clone(),
+            messages: swap_args.num_swaps as u64 * 2,
+            value: 0,
+            reserve: SWAP_ATTACHED_VALUE * 2,
+            deploys_itself: false,
+        })
+        .collect();
+    preflight::check(&client, &common_args, &plan).await?;
+
+    let app_cache = app_cache::AppCache::new(client.clone(), common_args.seed)
+        .load_states(pool_addresses)
+        .await
+        .load_tokens_and_token_pairs()
+        .await;
 
-    while let Some(recipient) = filtered_recipients.next().await {
+    log::info!("Loaded app cache");
+
+    let start = std::time::Instant::now();
+    let mut payloads = Vec::new();
+    for recipient in recipients {
         let payload_meta = app_cache.generate_payloads(recipient.clone(), swap_args.depth);
         let send_data = SendData::new(
             payload_meta,
@@ -139,6 +137,7 @@ pub async fn run(
         client,
         common_args.seed,
         common_args.clone(),
+        stats,
     );
 
     for payload in payloads {
@@ -157,7 +156,7 @@ async fn process_payload(mut send_data: SendData, test_env: TestEnv) {
         barrier,
         num_iterations: num_swaps,
         rate_limiter: rl,
-        counter,
+        stats,
         client,
         ..
     } = test_env;
@@ -180,13 +179,12 @@ async fn process_payload(mut send_data: SendData, test_env: TestEnv) {
             jitter,
             &mut generator,
             &test_env.args,
+            &stats,
         )
         .await
         {
             log::info!("Failed to send: {:?}", e);
-            continue;
         }
-        counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
     }
 
     barrier.wait().await;
@@ -200,6 +198,7 @@ async fn send_forward_and_backward(
     jitter: Jitter,
     generator: &mut tokio::sync::mpsc::Receiver<(PayloadMeta, PayloadMeta)>,
     app_args: &Args,
+    stats: &RunStats,
 ) -> Result<()> {
     let (forward_meta, backward_meta) = generator.recv().await.unwrap();
 
@@ -208,17 +207,23 @@ async fn send_forward_and_backward(
         payload: SendData,
         meta: PayloadMeta,
         state: Arc<AccountStuff>,
+        stats: RunStats,
     ) -> Result<()> {
-        send::send(
+        stats.record_built();
+        let start = Instant::now();
+        let result = send::send(
             &client,
             &payload.signer,
+            WalletKind::EverWallet,
             payload.sender_addr.clone(),
             meta.payload,
             meta.destination,
-            3_000_000_000,
+            SWAP_ATTACHED_VALUE,
             &state,
         )
-        .await
+        .await;
+        stats.record_send(start.elapsed(), &result);
+        result
     }
 
     async fn process_transaction(
@@ -229,10 +234,12 @@ async fn send_forward_and_backward(
         rl: &RateLimiter<NotKeyed, InMemoryState, DefaultClock>,
         jitter: Jitter,
         no_wait: bool,
+        stats: RunStats,
     ) -> Result<()> {
         rl.until_ready_with_jitter(jitter).await;
-        let handle =
-            tokio::spawn(async move { send_transaction(client, payload, meta, state).await });
+        let handle = tokio::spawn(async move {
+            send_transaction(client, payload, meta, state, stats).await
+        });
 
         if !no_wait {
             handle.await??;
@@ -250,6 +257,7 @@ async fn send_forward_and_backward(
         rl,
         jitter,
         app_args.no_wait,
+        stats.clone(),
     )
     .await?;
 
@@ -262,6 +270,7 @@ async fn send_forward_and_backward(
         rl,
         jitter,
         app_args.no_wait,
+        stats.clone(),
     )
     .await?;
 
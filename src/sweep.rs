@@ -0,0 +1,149 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use ed25519_dalek::Keypair;
+use everscale_rpc_client::RpcClient;
+use futures_util::StreamExt;
+use serde::Serialize;
+use ton_block::MsgAddressInt;
+use ton_types::BuilderData;
+
+use crate::deployments::{Deployments, Role};
+use crate::provision::fetch_balances;
+use crate::report::RunStats;
+use crate::wallets::{self, WalletKind};
+use crate::{send, Args};
+
+#[derive(Parser, Debug, Clone, Serialize)]
+pub struct SweepArgs {
+    #[clap(short, long)]
+    /// Number of sender wallets to sweep, indices `0..total_wallets`
+    total_wallets: u32,
+
+    #[clap(long)]
+    /// Address receiving the funds, the `sender-wallet` deployment by default
+    collector: Option<String>,
+
+    #[clap(long, default_value = "100000000")]
+    /// Nanotons left on every wallet, must cover the transfer fees
+    reserve: u64,
+
+    #[clap(long, default_value = "false")]
+    /// Only list what would be swept
+    dry_run: bool,
+
+    #[clap(long, default_value = "10")]
+    /// Transfers sent concurrently
+    max_concurrent: usize,
+}
+
+pub(crate) async fn run(
+    args: SweepArgs,
+    common_args: Args,
+    keypair: Arc<Keypair>,
+    client: RpcClient,
+    stats: RunStats,
+) -> Result<()> {
+    if common_args.fresh_wallets {
+        anyhow::bail!(
+            "Fresh wallets are generated per run, sweep them with --wallets-file <keys file>"
+        );
+    }
+    let deployments = Deployments::load(&common_args)?;
+    let collector = match &args.collector {
+        Some(address) => MsgAddressInt::from_str(address)
+            .map_err(|e| anyhow::anyhow!("Invalid collector address {address}: {e}"))?,
+        None => deployments.one(Role::SenderWallet)?.address,
+    };
+    log::info!("Sweeping {} wallets to {collector}", args.total_wallets);
+
+    let wallets =
+        wallets::load_senders(&common_args, &client, &keypair, args.total_wallets).await?;
+    let addresses: Vec<_> = wallets.iter().map(|w| w.address.clone()).collect();
+    let balances = fetch_balances(&client, &addresses).await?;
+
+    let mut not_deployed = 0;
+    let mut below_reserve = 0;
+    let mut jobs = Vec::new();
+    for (i, (wallet, account)) in wallets.into_iter().zip(balances).enumerate() {
+        // Funded uninitialized EverWallets still sweep, the transfer carries their StateInit.
+        let can_send =
+            account.deployed || (account.balance > 0 && wallet.kind == WalletKind::EverWallet);
+        match account.balance {
+            _ if !can_send => not_deployed += 1,
+            balance if balance <= args.reserve as u128 => below_reserve += 1,
+            balance => jobs.push((i, wallet, balance as u64 - args.reserve)),
+        }
+    }
+    let planned: u64 = jobs.iter().map(|(_, _, amount)| amount).sum();
+    let total_jobs = jobs.len() as u64;
+
+    if args.dry_run {
+        for (i, wallet, amount) in &jobs {
+            log::info!("{i:>6} {}: {amount}", wallet.address);
+        }
+        log::info!(
+            "Dry run: would sweep {planned} nanotons from {} wallets, {not_deployed} not deployed, \
+            {below_reserve} at or below the reserve",
+            jobs.len()
+        );
+        return Ok(());
+    }
+
+    let recovered = AtomicU64::new(0);
+    let swept = AtomicU64::new(0);
+    futures_util::stream::iter(jobs)
+        .for_each_concurrent(args.max_concurrent, |(i, wallet, amount)| {
+            let (client, collector, stats, recovered, swept) =
+                (&client, &collector, &stats, &recovered, &swept);
+            async move {
+                let result: Result<()> = async {
+                    let state = client
+                        .get_contract_state(&wallet.address, None)
+                        .await?
+                        .context("Wallet state disappeared")?
+                        .account;
+                    stats.record_built();
+                    let started = Instant::now();
+                    let result = send::send(
+                        client,
+                        &wallet.keypair,
+                        wallet.kind,
+                        wallet.address.clone(),
+                        BuilderData::new(),
+                        collector.clone(),
+                        amount,
+                        &state,
+                    )
+                    .await;
+                    stats.record_send(started.elapsed(), &result);
+                    result
+                }
+                .await;
+
+                match result {
+                    Ok(()) => {
+                        recovered.fetch_add(amount, Ordering::Relaxed);
+                        swept.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        log::error!("Failed to sweep wallet {i} ({}): {e:?}", wallet.address)
+                    }
+                }
+            }
+        })
+        .await;
+
+    let swept = swept.into_inner();
+    log::info!(
+        "Recovered {} of {planned} nanotons from {swept} wallets, {} failed, \
+        {not_deployed} not deployed, {below_reserve} at or below the reserve",
+        recovered.into_inner(),
+        total_jobs - swept
+    );
+    Ok(())
+}
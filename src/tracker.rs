@@ -0,0 +1,263 @@
+//! Resolves broadcast messages to their transactions, enabled with `--track-messages`.
+//!
+//! Every message signed by [`send::send_batch`](crate::send::send_batch) is registered with its
+//! hash and expiration. A background task looks up the transaction created by each pending
+//! message until it is found or the message expires. Lookups are repeated every
+//! [`POLL_INTERVAL`], so inclusion latency has the same resolution. At most
+//! [`MAX_LOOKUPS_PER_POLL`] messages are looked up per round, the rest wait for the next ones.
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use everscale_rpc_client::RpcClient;
+use futures_util::StreamExt;
+use histogram::AtomicHistogram;
+use serde::Serialize;
+use ton_block::{Transaction, TransactionDescr};
+use ton_types::UInt256;
+
+use crate::endpoints;
+use crate::report::{latency_histogram, LatencyReport};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Transactions can show up in the API a bit after the message expiration.
+const EXPIRATION_GRACE: Duration = Duration::from_secs(10);
+const MAX_CONCURRENT_LOOKUPS: usize = 100;
+const MAX_LOOKUPS_PER_POLL: usize = 2_000;
+
+#[derive(Clone)]
+struct Pending {
+    hash: UInt256,
+    sent_at: Instant,
+    expire_at: u32,
+}
+
+struct Tracker {
+    pending: Mutex<Vec<Pending>>,
+    delivered: AtomicU64,
+    failed: AtomicU64,
+    expired: AtomicU64,
+    inclusion: AtomicHistogram,
+}
+
+static TRACKER: OnceLock<Tracker> = OnceLock::new();
+
+/// Outcome counts of the tracked messages.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutcomeReport {
+    /// Included and successfully executed.
+    pub delivered: u64,
+    /// Included but the transaction was aborted.
+    pub failed: u64,
+    /// Never included before the expiration.
+    pub expired: u64,
+    /// Still pending when the run finished.
+    pub unresolved: u64,
+    /// Time from broadcast to the transaction being found.
+    pub inclusion_latency: Option<LatencyReport>,
+}
+
+pub fn start(client: RpcClient) {
+    if TRACKER.set(Tracker::new()).is_err() {
+        return;
+    }
+    tokio::spawn(async move {
+        let client = &client;
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(tracker) = TRACKER.get() else {
+                return;
+            };
+            tracker
+                .poll(move |hash| async move {
+                    endpoints::call(client, |client| async move {
+                        client.get_dst_transaction(hash.as_slice()).await
+                    })
+                    .await
+                })
+                .await;
+        }
+    });
+    log::info!("Tracking the outcome of every message");
+}
+
+/// Registers a broadcast message, does nothing unless tracking is started.
+pub fn track(hash: UInt256, expire_at: u32) {
+    if let Some(tracker) = TRACKER.get() {
+        tracker.pending.lock().unwrap().push(Pending {
+            hash,
+            sent_at: Instant::now(),
+            expire_at,
+        });
+    }
+}
+
+/// Waits until every tracked message is resolved or `timeout` passes.
+pub async fn finish(timeout: Duration) -> Option<OutcomeReport> {
+    Some(TRACKER.get()?.finish(timeout).await)
+}
+
+impl Tracker {
+    fn new() -> Self {
+        Self {
+            pending: Mutex::new(Vec::new()),
+            delivered: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+            inclusion: latency_histogram(),
+        }
+    }
+
+    async fn finish(&self, timeout: Duration) -> OutcomeReport {
+        let deadline = Instant::now() + timeout;
+        let mut unresolved = self.pending.lock().unwrap().len();
+        if unresolved > 0 {
+            log::info!("Waiting for {unresolved} messages to be resolved");
+        }
+        while unresolved > 0 && Instant::now() < deadline {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            unresolved = self.pending.lock().unwrap().len();
+        }
+
+        let report = OutcomeReport {
+            delivered: self.delivered.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            unresolved: unresolved as u64,
+            inclusion_latency: LatencyReport::from_histogram(&self.inclusion.load()),
+        };
+        log::info!(
+            "Message outcomes: {} delivered, {} failed, {} expired, {} unresolved",
+            report.delivered,
+            report.failed,
+            report.expired,
+            report.unresolved
+        );
+        report
+    }
+
+    /// Looks up the oldest pending messages. They stay pending until resolved, so
+    /// [`Tracker::finish`] never misses a lookup in progress, and the ones still unresolved move
+    /// to the back of the queue.
+    async fn poll<F, Fut>(&self, lookup: F)
+    where
+        F: Fn(UInt256) -> Fut,
+        Fut: Future<Output = Result<Option<Transaction>>>,
+    {
+        let batch: Vec<_> = {
+            let pending = self.pending.lock().unwrap();
+            pending.iter().take(MAX_LOOKUPS_PER_POLL).cloned().collect()
+        };
+        if batch.is_empty() {
+            return;
+        }
+        let looked_up = batch.len();
+
+        let resolved: HashSet<UInt256> = futures_util::stream::iter(batch)
+            .map(|message| {
+                let lookup = lookup(message.hash.clone());
+                async move {
+                    match lookup.await {
+                        Ok(Some(transaction)) => {
+                            let elapsed = message.sent_at.elapsed();
+                            let _ = self.inclusion.increment(elapsed.as_nanos() as u64);
+                            if is_aborted(&transaction) {
+                                self.failed.fetch_add(1, Ordering::Relaxed);
+                            } else {
+                                self.delivered.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Some(message.hash)
+                        }
+                        Ok(None) if is_expired(message.expire_at) => {
+                            self.expired.fetch_add(1, Ordering::Relaxed);
+                            Some(message.hash)
+                        }
+                        Ok(None) => None,
+                        Err(e) => {
+                            log::debug!(
+                                "Failed to look up message {}: {e:?}",
+                                message.hash.to_hex_string()
+                            );
+                            None
+                        }
+                    }
+                }
+            })
+            .buffer_unordered(MAX_CONCURRENT_LOOKUPS)
+            .filter_map(|hash| async move { hash })
+            .collect()
+            .await;
+
+        // New messages are only appended, so the batch is still at the front.
+        let mut pending = self.pending.lock().unwrap();
+        let mut front = 0;
+        pending.retain(|message| {
+            let in_batch = front < looked_up;
+            front += 1;
+            !(in_batch && resolved.contains(&message.hash))
+        });
+        let unresolved = looked_up - resolved.len();
+        pending.rotate_left(unresolved.min(pending.len()));
+    }
+}
+
+fn is_expired(expire_at: u32) -> bool {
+    nekoton_utils::now_sec_u64() > expire_at as u64 + EXPIRATION_GRACE.as_secs()
+}
+
+fn is_aborted(transaction: &Transaction) -> bool {
+    matches!(
+        transaction.read_description(),
+        Ok(TransactionDescr::Ordinary(description)) if description.aborted
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_finish_counts_lookups_in_progress() {
+        let tracker = Tracker::new();
+        for i in 0..3 {
+            tracker.pending.lock().unwrap().push(Pending {
+                hash: UInt256::from([i; 32]),
+                sent_at: Instant::now(),
+                expire_at: u32::MAX,
+            });
+        }
+
+        let slow_lookup = |_: UInt256| async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok::<_, anyhow::Error>(None)
+        };
+        let (_, report) = tokio::join!(tracker.poll(slow_lookup), tracker.finish(Duration::ZERO));
+        assert_eq!(report.unresolved, 3);
+
+        // The found message is dropped, the others stay pending in order.
+        tracker.pending.lock().unwrap().push(Pending {
+            hash: UInt256::from([9; 32]),
+            sent_at: Instant::now(),
+            expire_at: u32::MAX,
+        });
+        tracker
+            .poll(|hash| async move {
+                Ok::<_, anyhow::Error>((hash == UInt256::from([1; 32])).then(Transaction::default))
+            })
+            .await;
+        let order: Vec<_> = tracker
+            .pending
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|p| p.hash.as_slice()[0])
+            .collect();
+        assert_eq!(order, [0, 2, 9]);
+        assert_eq!(tracker.delivered.load(Ordering::Relaxed), 1);
+    }
+}
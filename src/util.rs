@@ -1,3 +1,4 @@
+use crate::report::RunStats;
 use crate::Args;
 use everscale_rpc_client::RpcClient;
 use governor::clock::DefaultClock;
@@ -16,6 +17,7 @@ pub struct TestEnv {
     pub num_iterations: u32,
     pub rate_limiter: Arc<RateLimiter<NotKeyed, InMemoryState, DefaultClock>>,
     pub counter: Arc<AtomicU64>,
+    pub stats: RunStats,
     pub client: RpcClient,
     pub seed: Option<u64>,
     pub args: Args,
@@ -29,6 +31,7 @@ impl TestEnv {
         client: RpcClient,
         seed: Option<u64>,
         args: Args,
+        stats: RunStats,
     ) -> Self {
         let barrier = Barrier::new(num_wallets + 1);
         let barrier = Arc::new(barrier);
@@ -37,12 +40,13 @@ impl TestEnv {
             .allow_burst(NonZeroU32::new(rps / 10).unwrap());
 
         let rate_limiter = Arc::new(governor::RateLimiter::direct(quota));
-        let counter = Arc::new(AtomicU64::new(0));
+        stats.set_target_rps(rps as u64);
         TestEnv {
             barrier,
             num_iterations,
             rate_limiter,
-            counter,
+            counter: stats.sent.clone(),
+            stats,
             client,
             seed,
             args,
@@ -51,13 +55,15 @@ impl TestEnv {
 
     pub fn spawn_progress_printer(&self) -> JoinHandle<()> {
         let counter = self.counter.clone();
+        let transfers = self.stats.transfers.clone();
         tokio::spawn(async move {
             let start = std::time::Instant::now();
             loop {
                 tokio::time::sleep(Duration::from_secs(5)).await;
                 log::info!(
-                    "Sent: {} transactions in {} seconds",
+                    "Sent: {} transactions ({} transfers) in {} seconds",
                     counter.load(std::sync::atomic::Ordering::Relaxed),
+                    transfers.load(std::sync::atomic::Ordering::Relaxed),
                     start.elapsed().as_secs()
                 );
             }
@@ -0,0 +1,391 @@
+//! Sender wallets of the transfer tests.
+//!
+//! EverWallets come from the `WalletFactory` deployment and share the main key. Other wallet
+//! types are derived from the seed phrase, wallet `i` uses the key of account `i`. With
+//! `--fresh-wallets` new keys are generated for every run and their EverWallets are deployed
+//! by the first transfer. The keys are written to a wallets file before the wallets are funded,
+//! so `sweep --wallets-file` can recover what is left.
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey};
+use everscale_rpc_client::RpcClient;
+use nekoton::core::ton_wallet::{self, MultisigType, WalletType};
+use serde::{Deserialize, Serialize};
+use ton_block::MsgAddressInt;
+use ton_types::BuilderData;
+
+use crate::deployments::{Deployments, Role};
+use crate::preflight::{self, SenderPlan};
+use crate::provision::{fetch_balances, get_wallets};
+use crate::send::{self, Transfer};
+use crate::Args;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WalletKind {
+    EverWallet,
+    WalletV3,
+    HighloadWalletV2,
+    SafeMultisig,
+    SafeMultisig24h,
+    SetcodeMultisig,
+    SetcodeMultisig24h,
+    BridgeMultisig,
+    Surf,
+    Multisig2,
+    Multisig21,
+}
+
+impl WalletKind {
+    pub fn wallet_type(self) -> WalletType {
+        match self {
+            WalletKind::EverWallet => WalletType::EverWallet,
+            WalletKind::WalletV3 => WalletType::WalletV3,
+            WalletKind::HighloadWalletV2 => WalletType::HighloadWalletV2,
+            WalletKind::SafeMultisig => WalletType::Multisig(MultisigType::SafeMultisigWallet),
+            WalletKind::SafeMultisig24h => {
+                WalletType::Multisig(MultisigType::SafeMultisigWallet24h)
+            }
+            WalletKind::SetcodeMultisig => {
+                WalletType::Multisig(MultisigType::SetcodeMultisigWallet)
+            }
+            WalletKind::SetcodeMultisig24h => {
+                WalletType::Multisig(MultisigType::SetcodeMultisigWallet24h)
+            }
+            WalletKind::BridgeMultisig => WalletType::Multisig(MultisigType::BridgeMultisigWallet),
+            WalletKind::Surf => WalletType::Multisig(MultisigType::SurfWallet),
+            WalletKind::Multisig2 => WalletType::Multisig(MultisigType::Multisig2),
+            WalletKind::Multisig21 => WalletType::Multisig(MultisigType::Multisig2_1),
+        }
+    }
+
+    /// Outgoing messages a single external message of the wallet can carry.
+    pub fn max_gifts(self) -> usize {
+        match self.wallet_type() {
+            WalletType::EverWallet | WalletType::WalletV3 => 4,
+            WalletType::HighloadWalletV2 => 254,
+            _ => 1,
+        }
+    }
+
+    pub fn compute_address(self, keypair: &Keypair) -> MsgAddressInt {
+        ton_wallet::compute_address(&keypair.public, self.wallet_type(), 0)
+    }
+}
+
+#[derive(Clone)]
+pub struct Sender {
+    pub address: MsgAddressInt,
+    pub kind: WalletKind,
+    pub keypair: Arc<Keypair>,
+    /// Factory or account index of the wallet, tags its transfers.
+    pub index: u32,
+    seq: Arc<AtomicU64>,
+}
+
+impl Sender {
+    fn new(address: MsgAddressInt, kind: WalletKind, keypair: Arc<Keypair>, index: u32) -> Self {
+        Self {
+            address,
+            kind,
+            keypair,
+            index,
+            seq: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Body of the next plain transfer, see [`send::transfer_payload`].
+    pub fn transfer_payload(&self) -> Result<BuilderData> {
+        send::transfer_payload(self.index, self.seq.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// How long fresh wallets may take to receive their funding.
+const FUNDING_TIMEOUT: Duration = Duration::from_secs(120);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Senders of a fleet test: `count` fresh wallets, the `--wallets-file` entries, otherwise
+/// `count` wallets of `--wallet-type`.
+pub async fn load_senders(
+    common_args: &Args,
+    client: &RpcClient,
+    keypair: &Arc<Keypair>,
+    count: u32,
+) -> Result<Vec<Sender>> {
+    if common_args.fresh_wallets {
+        return fresh_senders(common_args, client, keypair, count).await;
+    }
+    if let Some(path) = &common_args.wallets_file {
+        let senders = read_wallets_file(path)?;
+        log::info!("Loaded {} sender wallets from {:?}", senders.len(), path);
+        return Ok(senders);
+    }
+
+    let kind = common_args.wallet_type;
+    if kind == WalletKind::EverWallet {
+        let factory = Deployments::load(common_args)?
+            .one(Role::WalletFactory)?
+            .address;
+        let wallets = get_wallets(client, &factory, count, keypair)
+            .await
+            .context("Failed to get wallets")?;
+        return Ok(wallets
+            .into_iter()
+            .zip(0..)
+            .map(|(address, index)| Sender::new(address, kind, keypair.clone(), index))
+            .collect());
+    }
+
+    (0..count)
+        .map(|index| derived_sender(kind, index))
+        .collect()
+}
+
+/// Generates `count` keypairs, stores them and funds their EverWallet addresses from the
+/// `SenderWallet` deployment. The wallets stay uninitialized until their first transfer carries
+/// the StateInit.
+async fn fresh_senders(
+    common_args: &Args,
+    client: &RpcClient,
+    keypair: &Arc<Keypair>,
+    count: u32,
+) -> Result<Vec<Sender>> {
+    let master = Deployments::load(common_args)?
+        .one(Role::SenderWallet)?
+        .address;
+    let balance = common_args.fresh_wallet_balance;
+
+    let senders: Vec<_> = (0..count)
+        .map(|index| {
+            let keypair = random_keypair();
+            let address = WalletKind::EverWallet.compute_address(&keypair);
+            Sender::new(address, WalletKind::EverWallet, Arc::new(keypair), index)
+        })
+        .collect();
+    let keys_path = match &common_args.fresh_wallets_keys {
+        Some(path) => path.clone(),
+        None => common_args.project_root.join(format!(
+            "fresh-wallets-{}.keys",
+            nekoton_utils::now_sec_u64()
+        )),
+    };
+    write_keys_file(&keys_path, &master, &senders)?;
+    log::info!("Stored fresh wallet keys in {keys_path:?}, sweep them with --wallets-file");
+
+    // Every wallet is counted as a message of its own, an upper bound for batched transfers.
+    let plan = [SenderPlan {
+        wallet: master.clone(),
+        messages: count as u64,
+        value: balance,
+        reserve: 0,
+        deploys_itself: false,
+    }];
+    preflight::check(client, common_args, &plan).await?;
+
+    log::info!("Funding {count} fresh wallets with {balance} nanotons each from {master}");
+
+    let master_state = client
+        .get_contract_state(&master, None)
+        .await?
+        .with_context(|| format!("No state for {master}"))?
+        .account;
+    for chunk in senders.chunks(WalletKind::EverWallet.max_gifts()) {
+        let transfers = chunk
+            .iter()
+            .map(|sender| Transfer {
+                destination: sender.address.clone(),
+                amount: balance,
+                payload: BuilderData::new(),
+            })
+            .collect();
+        send::send_batch(
+            client,
+            keypair,
+            WalletKind::EverWallet,
+            master.clone(),
+            transfers,
+            &master_state,
+        )
+        .await
+        .context("Failed to fund fresh wallets")?;
+    }
+
+    let addresses: Vec<_> = senders.iter().map(|s| s.address.clone()).collect();
+    let deadline = Instant::now() + FUNDING_TIMEOUT;
+    loop {
+        let balances = fetch_balances(client, &addresses).await?;
+        // Fresh wallets stay uninitialized until their first transfer, only the balance counts.
+        let funded = balances
+            .iter()
+            .filter(|b| b.balance >= balance as u128)
+            .count();
+        if funded == senders.len() {
+            break;
+        }
+        if Instant::now() > deadline {
+            anyhow::bail!("Only {funded} of {count} fresh wallets were funded in time");
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    log::info!("Funded {count} fresh wallets");
+
+    Ok(senders)
+}
+
+fn random_keypair() -> Keypair {
+    let secret = SecretKey::from_bytes(&rand::random::<[u8; 32]>())
+        .expect("32 bytes are a valid secret key");
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+}
+
+/// Writes `ever-wallet key:<secret hex>` lines in the [`read_wallets_file`] format, refusing to
+/// overwrite an existing file.
+fn write_keys_file(path: &Path, master: &MsgAddressInt, senders: &[Sender]) -> Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create fresh wallet keys file {path:?}"))?;
+
+    let mut data = format!("# Fresh wallets funded from {master}\n");
+    for sender in senders {
+        data.push_str(&format!(
+            "ever-wallet key:{}\n",
+            hex::encode(sender.keypair.secret.as_bytes())
+        ));
+    }
+    file.write_all(data.as_bytes())
+        .and_then(|_| file.sync_all())
+        .with_context(|| format!("Failed to write fresh wallet keys file {path:?}"))
+}
+
+/// Fails early when a sender can't carry `gifts_per_message` transfers in one message.
+pub fn check_gifts_per_message(senders: &[Sender], gifts_per_message: u32) -> Result<()> {
+    if gifts_per_message == 0 {
+        anyhow::bail!("--gifts-per-message must be at least 1");
+    }
+    if let Some(sender) = senders
+        .iter()
+        .find(|s| s.kind.max_gifts() < gifts_per_message as usize)
+    {
+        anyhow::bail!(
+            "{:?} wallet {} carries at most {} transfers per message, \
+            lower --gifts-per-message",
+            sender.kind,
+            sender.address,
+            sender.kind.max_gifts()
+        );
+    }
+    Ok(())
+}
+
+/// Single sender of the latency test: the `SenderWallet` deployment for EverWallet, otherwise
+/// the `--wallet-type` wallet of the main key.
+pub fn main_sender(common_args: &Args, keypair: &Arc<Keypair>) -> Result<Sender> {
+    let kind = common_args.wallet_type;
+    let address = match kind {
+        WalletKind::EverWallet => {
+            Deployments::load(common_args)?
+                .one(Role::SenderWallet)?
+                .address
+        }
+        _ => kind.compute_address(keypair),
+    };
+    Ok(Sender::new(address, kind, keypair.clone(), 0))
+}
+
+/// Reads `<wallet-type> <account index>` or `<wallet-type> key:<secret hex>` lines, `#` starts
+/// a comment. Wallets given by key are indexed by their position in the file.
+fn read_wallets_file(path: &Path) -> Result<Vec<Sender>> {
+    let data = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read wallets file {path:?}"))?;
+
+    let mut senders = Vec::new();
+    for (line_no, line) in data.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parse = || -> Result<Sender> {
+            let mut parts = line.split_whitespace();
+            let kind = parts.next().context("Missing wallet type")?;
+            let kind = WalletKind::from_str(kind, true).map_err(|e| anyhow::anyhow!(e))?;
+            let wallet = parts.next().context("Missing account index or key")?;
+            if parts.next().is_some() {
+                anyhow::bail!("Unexpected trailing data");
+            }
+            match wallet.strip_prefix("key:") {
+                Some(secret) => keyed_sender(kind, secret, senders.len() as u32),
+                None => derived_sender(
+                    kind,
+                    wallet.parse().context("Invalid account index")?,
+                ),
+            }
+        };
+        senders.push(parse().with_context(|| format!("{path:?}:{}", line_no + 1))?);
+    }
+
+    if senders.is_empty() {
+        anyhow::bail!("No wallets in {path:?}");
+    }
+    Ok(senders)
+}
+
+fn keyed_sender(kind: WalletKind, secret: &str, index: u32) -> Result<Sender> {
+    let secret = hex::decode(secret).context("Invalid secret key hex")?;
+    let secret = SecretKey::from_bytes(&secret).context("Invalid secret key")?;
+    let public = PublicKey::from(&secret);
+    let keypair = Keypair { secret, public };
+    let address = kind.compute_address(&keypair);
+    Ok(Sender::new(address, kind, Arc::new(keypair), index))
+}
+
+fn derived_sender(kind: WalletKind, index: u32) -> Result<Sender> {
+    let phrase = dotenvy::var("BROXUS_PHRASE").context("SEED is not set")?;
+    let keypair = nekoton::crypto::derive_from_phrase(
+        &phrase,
+        nekoton::crypto::MnemonicType::Labs(index as _),
+    )
+    .context("Failed to derive keypair")?;
+    let address = kind.compute_address(&keypair);
+    Ok(Sender::new(address, kind, Arc::new(keypair), index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fresh_wallet_keys_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fresh.keys");
+        let master = MsgAddressInt::default();
+        let senders: Vec<_> = (0..3)
+            .map(|index| {
+                let keypair = random_keypair();
+                let address = WalletKind::EverWallet.compute_address(&keypair);
+                Sender::new(address, WalletKind::EverWallet, Arc::new(keypair), index)
+            })
+            .collect();
+
+        write_keys_file(&path, &master, &senders).unwrap();
+        let loaded = read_wallets_file(&path).unwrap();
+        let addresses = |senders: &[Sender]| -> Vec<_> {
+            senders.iter().map(|s| (s.address.clone(), s.index)).collect()
+        };
+        assert_eq!(addresses(&loaded), addresses(&senders));
+
+        // Keys of an earlier run are never overwritten.
+        assert!(write_keys_file(&path, &master, &senders).is_err());
+    }
+}
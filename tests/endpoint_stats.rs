@@ -0,0 +1,92 @@
+//! Runs `--endpoint-stats` against two mock nodes, needs `--features mock-node`.
+//!
+//! The endpoint pool is process-wide, so this lives apart from the concurrent runs of
+//! `tests/mock_node.rs`.
+
+use std::str::FromStr;
+
+use nekroddos::mock_node::MockNode;
+use serde_json::Value;
+use ton_block::{Account, CurrencyCollection, MsgAddressInt, StateInit};
+use ton_types::{BuilderData, Cell};
+
+const TEST_PHRASE: &str = "abandon abandon abandon abandon abandon abandon abandon abandon \
+    abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+    abandon abandon abandon abandon art";
+
+fn test_account(index: u32, code: Cell) -> Account {
+    let state_init = StateInit {
+        code: Some(code),
+        ..Default::default()
+    };
+    Account::active_by_init_code_hash(
+        MsgAddressInt::from_str(&format!("0:{index:064x}")).unwrap(),
+        CurrencyCollection::with_grams(100_000_000_000),
+        0,
+        state_init,
+        false,
+    )
+    .unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn endpoint_stats_count_requests_of_every_endpoint() {
+    let mut builder = BuilderData::new();
+    builder.append_u32(7).unwrap();
+    let code = builder.into_cell().unwrap();
+
+    let nodes = [
+        MockNode::start().await.unwrap(),
+        MockNode::start().await.unwrap(),
+    ];
+    for node in &nodes {
+        for i in 0..10 {
+            node.insert_account(test_account(i, code.clone())).unwrap();
+        }
+    }
+
+    let root = tempfile::tempdir().unwrap();
+    std::fs::write(
+        root.path().join(".env"),
+        format!("BROXUS_PHRASE=\"{TEST_PHRASE}\"\n"),
+    )
+    .unwrap();
+    let report_path = root.path().join("report.json");
+    let root_arg = root.path().to_string_lossy().to_string();
+    let report_arg = report_path.to_string_lossy().to_string();
+    let urls = nodes.each_ref().map(|node| node.url().to_string());
+    let code_hash = hex::encode(code.repr_hash().as_slice());
+
+    nekroddos::run_from([
+        "nekroddos",
+        "--project-root",
+        &root_arg,
+        "--endpoints",
+        &urls[0],
+        "--endpoints",
+        &urls[1],
+        "--report",
+        &report_arg,
+        "--endpoint-stats",
+        "accounts-dos",
+        "--code-hash",
+        &code_hash,
+        "--rps",
+        "40",
+        "--duration",
+        "2",
+    ])
+    .await
+    .unwrap();
+
+    let report: Value = serde_json::from_slice(&std::fs::read(&report_path).unwrap()).unwrap();
+    let stats = &report["endpoint_stats"];
+    for (node, url) in nodes.iter().zip(&urls) {
+        let endpoint = &stats[url.as_str()];
+        let ok = endpoint["ok"].as_u64().unwrap();
+        assert!(ok > 0, "{url} served nothing: {stats:#}");
+        assert_eq!(endpoint["failed"], 0);
+        // Only the load requests read states, each one is attributed to the node serving it.
+        assert_eq!(node.request_count("getContractState") as u64, ok);
+    }
+}
@@ -0,0 +1,464 @@
+//! Runs subcommands end-to-end against the in-process mock node, needs `--features mock-node`.
+//!
+//! Accounts are built on the fly: plain ones for `accounts-dos`, EverWallets of fixed keys and
+//! the default mainnet config for the transfer subcommands. `dag` and `swap` execute getters of
+//! the factory and DEX contracts, whose code is not in the repo, so they are ignored by default
+//! and run with `--ignored` once `NEKRODDOS_MOCK_FIXTURES` points to a directory with the
+//! [`MockNode::load_fixtures`] layout plus a `deployments/` tree. Wallets in the fixtures must
+//! belong to the key derived from [`TEST_PHRASE`].
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use ed25519_dalek::{PublicKey, SecretKey};
+use everscale_rpc_client::{ClientOptions, RpcClient};
+use nekoton::core::ton_wallet::{self, ever_wallet, WalletType};
+use nekroddos::mock_node::MockNode;
+use serde_json::Value;
+use ton_block::{
+    Account, CurrencyCollection, ExternalInboundMessageHeader, Message, MsgAddressInt,
+    Serializable, StateInit,
+};
+use ton_types::{BuilderData, Cell, SliceData};
+
+const TEST_PHRASE: &str = "abandon abandon abandon abandon abandon abandon abandon abandon \
+    abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon \
+    abandon abandon abandon abandon art";
+
+fn project_root() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(
+        dir.path().join(".env"),
+        format!("BROXUS_PHRASE=\"{TEST_PHRASE}\"\n"),
+    )
+    .unwrap();
+    std::fs::create_dir(dir.path().join("deployments")).unwrap();
+    dir
+}
+
+fn test_code(tag: u32) -> Cell {
+    let mut builder = BuilderData::new();
+    builder.append_u32(tag).unwrap();
+    builder.into_cell().unwrap()
+}
+
+fn test_address(index: u32) -> MsgAddressInt {
+    MsgAddressInt::from_str(&format!("0:{index:064x}")).unwrap()
+}
+
+fn test_account(address: MsgAddressInt, code: Cell) -> Account {
+    let state_init = StateInit {
+        code: Some(code),
+        ..Default::default()
+    };
+    funded_account(address, state_init)
+}
+
+fn funded_account(address: MsgAddressInt, state_init: StateInit) -> Account {
+    Account::active_by_init_code_hash(
+        address,
+        CurrencyCollection::with_grams(100_000_000_000),
+        0,
+        state_init,
+        false,
+    )
+    .unwrap()
+}
+
+async fn run(root: &Path, node: &MockNode, args: &[&str]) -> anyhow::Result<Value> {
+    let report = root.join("report.json");
+    let root_arg = root.to_string_lossy().to_string();
+    let endpoint = node.url().to_string();
+    let report_arg = report.to_string_lossy().to_string();
+
+    let mut argv = vec![
+        "nekroddos",
+        "--project-root",
+        &root_arg,
+        "--endpoints",
+        &endpoint,
+        "--report",
+        &report_arg,
+    ];
+    argv.extend_from_slice(args);
+    nekroddos::run_from(argv).await?;
+
+    Ok(serde_json::from_slice(&std::fs::read(report)?)?)
+}
+
+fn counts(report: &Value) -> (u64, u64) {
+    let counts = &report["counts"];
+    (
+        counts["sent"].as_u64().unwrap(),
+        counts["failed"].as_u64().unwrap(),
+    )
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rpc_client_reads_and_sends() {
+    let node = MockNode::start().await.unwrap();
+    let address = test_address(1);
+    node.insert_account(test_account(address.clone(), test_code(1)))
+        .unwrap();
+
+    let client = RpcClient::new(vec![node.url()], ClientOptions::default())
+        .await
+        .unwrap();
+
+    let state = client
+        .get_contract_state(&address, None)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(state.account.addr, address);
+    assert_eq!(state.account.storage.balance.grams.as_u128(), 100_000_000_000);
+    assert!(client
+        .get_contract_state(&test_address(2), None)
+        .await
+        .unwrap()
+        .is_none());
+
+    let message = Message::with_ext_in_header_and_body(
+        ExternalInboundMessageHeader {
+            dst: address.clone(),
+            ..Default::default()
+        },
+        SliceData::load_builder(BuilderData::new()).unwrap(),
+    );
+    client.broadcast_message(message).await.unwrap();
+
+    assert_eq!(node.sent_messages().len(), 1);
+    assert_eq!(
+        node.account(&address).unwrap().storage.last_trans_lt,
+        state.account.storage.last_trans_lt + 1
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn accounts_dos_paginates_and_fetches_states() {
+    let node = MockNode::start().await.unwrap();
+    let code = test_code(7);
+    for i in 0..150 {
+        node.insert_account(test_account(test_address(i), code.clone()))
+            .unwrap();
+    }
+    // Must not be picked up by the code hash lookup.
+    node.insert_account(test_account(test_address(1000), test_code(8)))
+        .unwrap();
+
+    let root = project_root();
+    let code_hash = hex::encode(code.repr_hash().as_slice());
+    let report = run(
+        root.path(),
+        &node,
+        &[
+            "accounts-dos",
+            "--code-hash",
+            &code_hash,
+            "--rps",
+            "50",
+            "--duration",
+            "2",
+        ],
+    )
+    .await
+    .unwrap();
+
+    let (sent, failed) = counts(&report);
+    assert!(sent > 0);
+    assert_eq!(failed, 0);
+    assert_eq!(report["command"], "accounts-dos");
+    assert_eq!(node.request_count("getAccountsByCodeHash"), 2);
+    assert_eq!(node.request_count("getContractState") as u64, sent);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn accounts_dos_caches_merged_accounts() {
+    let node = MockNode::start().await.unwrap();
+    let (code_a, code_b) = (test_code(11), test_code(12));
+    for i in 0..5 {
+        node.insert_account(test_account(test_address(i), code_a.clone()))
+            .unwrap();
+        node.insert_account(test_account(test_address(100 + i), code_b.clone()))
+            .unwrap();
+    }
+
+    let root = project_root();
+    let accounts_file = root.path().join("accounts.txt");
+    let accounts_arg = accounts_file.to_string_lossy().to_string();
+    let code_hashes = format!(
+        "{},{}",
+        hex::encode(code_a.repr_hash().as_slice()),
+        hex::encode(code_b.repr_hash().as_slice())
+    );
+    let args = [
+        "accounts-dos",
+        "--code-hash",
+        &code_hashes,
+        "--accounts-file",
+        &accounts_arg,
+        "--rps",
+        "20",
+        "--duration",
+        "1",
+    ];
+
+    run(root.path(), &node, &args).await.unwrap();
+    assert_eq!(node.request_count("getAccountsByCodeHash"), 2);
+    let saved = std::fs::read_to_string(&accounts_file).unwrap();
+    assert_eq!(saved.lines().filter(|l| !l.starts_with('#')).count(), 10);
+
+    // The second run reads the file, no pagination even without code hashes.
+    run(root.path(), &node, &args).await.unwrap();
+    run(
+        root.path(),
+        &node,
+        &["accounts-dos", "--accounts-file", &accounts_arg, "--duration", "1"],
+    )
+    .await
+    .unwrap();
+    assert_eq!(node.request_count("getAccountsByCodeHash"), 2);
+
+    // A list fetched for other code hashes is refused.
+    let code_a = hex::encode(code_a.repr_hash().as_slice());
+    let mut args = args;
+    args[2] = code_a.as_str();
+    assert!(run(root.path(), &node, &args).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn accounts_dos_runs_weighted_mix() {
+    let node = MockNode::start().await.unwrap();
+    let code = test_code(9);
+    for i in 0..10 {
+        node.insert_account(test_account(test_address(i), code.clone()))
+            .unwrap();
+    }
+
+    let root = project_root();
+    let code_hash = hex::encode(code.repr_hash().as_slice());
+    let report = run(
+        root.path(),
+        &node,
+        &[
+            "accounts-dos",
+            "--code-hash",
+            &code_hash,
+            "--rps",
+            "100",
+            "--duration",
+            "2",
+            "--mix",
+            "contract-state=1,transactions=1,accounts-by-code-hash=1,send-message=1",
+        ],
+    )
+    .await
+    .unwrap();
+
+    let (sent, failed) = counts(&report);
+    assert_eq!(failed, 0);
+    let methods = report["methods"].as_object().unwrap();
+    let total: u64 = methods.values().map(|m| m["ok"].as_u64().unwrap()).sum();
+    assert_eq!(total, sent);
+    for method in ["getContractState", "getTransactionsList", "sendMessage"] {
+        assert_eq!(
+            methods[method]["ok"].as_u64().unwrap() as usize,
+            node.request_count(method)
+        );
+    }
+    // One more page request for the initial account lookup.
+    assert_eq!(
+        methods["getAccountsByCodeHash"]["ok"].as_u64().unwrap() as usize + 1,
+        node.request_count("getAccountsByCodeHash")
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn accounts_dos_fails_without_accounts() {
+    let node = MockNode::start().await.unwrap();
+    let root = project_root();
+    let code_hash = hex::encode([0u8; 32]);
+    let accounts_file = root.path().join("accounts.txt");
+    let accounts_arg = accounts_file.to_string_lossy().to_string();
+
+    let result = run(
+        root.path(),
+        &node,
+        &[
+            "accounts-dos",
+            "--code-hash",
+            &code_hash,
+            "--accounts-file",
+            &accounts_arg,
+            "--duration",
+            "1",
+        ],
+    )
+    .await;
+    assert!(result.is_err());
+    assert!(!accounts_file.exists());
+}
+
+/// Prepares a project root and a node from `NEKRODDOS_MOCK_FIXTURES`.
+async fn with_fixtures() -> (tempfile::TempDir, MockNode) {
+    let fixtures = std::env::var_os("NEKRODDOS_MOCK_FIXTURES")
+        .map(PathBuf::from)
+        .expect("NEKRODDOS_MOCK_FIXTURES must point to the fixture directory");
+
+    let node = MockNode::start().await.unwrap();
+    node.load_fixtures(&fixtures).unwrap();
+
+    let root = project_root();
+    let source = fixtures.join("deployments");
+    for entry in walkdir::WalkDir::new(&source) {
+        let entry = entry.unwrap();
+        let target = root
+            .path()
+            .join("deployments")
+            .join(entry.path().strip_prefix(&source).unwrap());
+        if entry.file_type().is_dir() {
+            std::fs::create_dir_all(target).unwrap();
+        } else {
+            std::fs::copy(entry.path(), target).unwrap();
+        }
+    }
+
+    (root, node)
+}
+
+async fn run_with_fixtures(args: &[&str]) {
+    let (root, node) = with_fixtures().await;
+
+    let report = run(root.path(), &node, args).await.unwrap();
+    let (sent, failed) = counts(&report);
+    assert!(sent > 0, "nothing was sent: {report:#}");
+    assert_eq!(failed, 0, "some sends failed: {report:#}");
+    assert!(!node.sent_messages().is_empty());
+}
+
+/// Prepares a node with `count` funded EverWallets of fixed keys and the default mainnet config,
+/// whose config contract serves the masterchain state. Returns the `--wallets-file` listing the
+/// wallets.
+async fn with_wallets(count: u8) -> (tempfile::TempDir, MockNode, String) {
+    let node = MockNode::start().await.unwrap();
+    let config = ton_executor::BlockchainConfig::default();
+    let config = config.raw_config();
+    node.set_config(config.serialize().unwrap(), 42);
+    let config_contract =
+        MsgAddressInt::with_standart(None, -1, config.config_addr.clone().into()).unwrap();
+    node.insert_account(test_account(config_contract, test_code(0)))
+        .unwrap();
+
+    let root = project_root();
+    let mut wallets = String::new();
+    for i in 1..=count {
+        let secret = SecretKey::from_bytes(&[i; 32]).unwrap();
+        let public = PublicKey::from(&secret);
+        let address = ton_wallet::compute_address(&public, WalletType::EverWallet, 0);
+        let state_init = ever_wallet::make_state_init(&public).unwrap();
+        node.insert_account(funded_account(address, state_init))
+            .unwrap();
+        let secret = hex::encode(secret.as_bytes());
+        writeln!(wallets, "ever-wallet key:{secret}").unwrap();
+    }
+    let wallets_file = root.path().join("wallets.txt");
+    std::fs::write(&wallets_file, wallets).unwrap();
+
+    (root, node, wallets_file.to_string_lossy().to_string())
+}
+
+/// Runs a transfer subcommand from the wallets of [`with_wallets`], every message must be sent.
+async fn run_with_wallets(args: &[&str]) {
+    let (root, node, wallets_file) = with_wallets(2).await;
+
+    let mut argv = vec!["--wallets-file", wallets_file.as_str()];
+    argv.extend_from_slice(args);
+    let report = run(root.path(), &node, &argv).await.unwrap();
+    let (sent, failed) = counts(&report);
+    assert!(sent > 0, "nothing was sent: {report:#}");
+    assert_eq!(failed, 0, "some sends failed: {report:#}");
+    assert!(!node.sent_messages().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn send_from_wallets() {
+    run_with_wallets(&[
+        "send",
+        "--total-wallets",
+        "2",
+        "--rps",
+        "10",
+        "--num-iterations",
+        "2",
+    ])
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn rand_send_from_wallets() {
+    run_with_wallets(&[
+        "rand-send",
+        "--total-wallets",
+        "2",
+        "--rps",
+        "5",
+        "--num-seconds",
+        "2",
+        "--from-rps",
+        "1",
+        "--to-rps",
+        "10",
+    ])
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn send_to_targets_from_wallets() {
+    let targets = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(targets.path(), format!("{}\n", test_address(1))).unwrap();
+    let targets = targets.path().to_string_lossy().to_string();
+
+    run_with_wallets(&[
+        "send-to-targets",
+        "--total-wallets",
+        "2",
+        "--rps",
+        "10",
+        "--num-iterations",
+        "2",
+        "--amount",
+        "1000000",
+        "--targets-file",
+        &targets,
+    ])
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn latency_from_wallets() {
+    run_with_wallets(&["latency", "--num-txs", "3", "--rps", "10", "--senders", "2"]).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs NEKRODDOS_MOCK_FIXTURES"]
+async fn dag_with_fixtures() {
+    run_with_fixtures(&[
+        "dag",
+        "--total-wallets",
+        "2",
+        "--rps",
+        "10",
+        "--num-iterations",
+        "2",
+        "--payload-size",
+        "32",
+    ])
+    .await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs NEKRODDOS_MOCK_FIXTURES"]
+async fn swap_with_fixtures() {
+    run_with_fixtures(&["swap", "--num-swaps", "1", "--rps", "10", "--depth", "2"]).await;
+}